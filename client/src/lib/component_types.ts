@@ -1,5 +1,6 @@
 export interface searchRes {
 	title: string,
 	url: string,
+	path?: string | null,
 	summary: string
 }
@@ -0,0 +1,379 @@
+// admin api to manage the sites table at runtime
+// every change is announced on the control exchange, crawler and parser reload the site from the table
+use std::env;
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::{get, patch, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::net::TcpListener;
+use tower_http::trace::TraceLayer;
+use tracing::{error, info};
+use url::Url;
+
+use crate::config::DEFAULT_BUDGET_CYCLE;
+use db::{Db, SiteChanges, SiteRow, TrapRow};
+use utils::{site_key, ControlMessage, RabbitMQ, Scope};
+
+#[derive(Debug, Clone)]
+pub struct AdminState {
+    db: Db,
+    control: RabbitMQ,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SiteResponse {
+    host: String,
+    url: String,
+    depth: Option<i32>,
+    rps: Option<i32>,
+    max_body_size: Option<i64>,
+    paused: bool,
+    path_prefix: String,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    domains: Vec<String>,
+    external_hops: i32,
+    weight: f32,
+    max_pages: Option<i32>,
+    max_bytes: Option<i64>,
+    max_crawl_secs: Option<i32>,
+    budget_cycle_secs: i32,
+}
+
+impl From<SiteRow> for SiteResponse {
+    fn from(row: SiteRow) -> Self {
+        let path_prefix = row.path_prefix.unwrap_or_else(|| {
+            Url::parse(&row.url).map_or("/".to_string(), |u| Scope::default_prefix(&u))
+        });
+
+        SiteResponse {
+            host: row.host,
+            url: row.url,
+            depth: row.depth,
+            rps: row.rps,
+            max_body_size: row.max_body_size,
+            paused: row.paused,
+            path_prefix,
+            include: row.include_patterns,
+            exclude: row.exclude_patterns,
+            domains: row.domains,
+            external_hops: row.external_hops,
+            weight: row.weight,
+            max_pages: row.max_pages,
+            max_bytes: row.max_bytes,
+            max_crawl_secs: row.max_crawl_secs,
+            budget_cycle_secs: row.budget_cycle_secs,
+        }
+    }
+}
+
+// urls of a host rejected as crawl traps, by reason
+#[derive(Debug, Serialize)]
+pub struct TrapResponse {
+    host: String,
+    reason: String,
+    rejected: i64,
+}
+
+impl From<TrapRow> for TrapResponse {
+    fn from(row: TrapRow) -> Self {
+        TrapResponse {
+            host: row.host,
+            reason: row.reason,
+            rejected: row.rejected,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewSite {
+    url: String,
+    depth: Option<u32>,
+    rps: Option<u32>,
+    max_body_size: Option<u64>,
+    path_prefix: Option<String>,
+    #[serde(default)]
+    include: Vec<String>, // globs, or regexes prefixed with "re:"
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default)]
+    domains: Vec<String>, // e.g. "*.rust-lang.org"
+    #[serde(default)]
+    external_hops: u32,
+    weight: Option<f32>,
+    max_pages: Option<u32>, // per budget cycle
+    max_bytes: Option<u64>,
+    max_crawl_secs: Option<u32>,
+    budget_cycle_secs: Option<u32>,
+}
+
+// a missing field is left as is, null clears the fields that can be unset
+#[derive(Debug, Deserialize)]
+pub struct SiteUpdate {
+    #[serde(default, deserialize_with = "nullable")]
+    depth: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    rps: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    max_body_size: Option<Option<u64>>,
+    #[serde(default, deserialize_with = "nullable")]
+    path_prefix: Option<Option<String>>,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    domains: Option<Vec<String>>,
+    external_hops: Option<u32>,
+    weight: Option<f32>,
+    #[serde(default, deserialize_with = "nullable")]
+    max_pages: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    max_bytes: Option<Option<u64>>,
+    #[serde(default, deserialize_with = "nullable")]
+    max_crawl_secs: Option<Option<u32>>,
+    budget_cycle_secs: Option<u32>,
+}
+
+// tells a field set to null, Some(None), apart from a missing one, None through `default`
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+// listens on ADMIN_ADDR, localhost only by default as there is no auth
+pub async fn serve(db: Db, control: RabbitMQ) -> Result<()> {
+    let router = Router::new()
+        .route("/sites", get(list_sites).post(add_site))
+        .route("/sites/:host", patch(update_site).delete(remove_site))
+        .route("/sites/:host/pause", post(pause_site))
+        .route("/sites/:host/resume", post(resume_site))
+        .route("/traps", get(list_traps))
+        .layer(TraceLayer::new_for_http())
+        .with_state(AdminState { db, control });
+
+    let addr: SocketAddr = env::var("ADMIN_ADDR")
+        .unwrap_or("127.0.0.1:8081".to_string())
+        .parse()?;
+    info!("admin api is listening on http://{}", addr);
+
+    let listener = TcpListener::bind(addr).await?;
+    axum::serve(listener, router).await?;
+
+    Ok(())
+}
+
+fn internal_error(e: impl std::fmt::Display) -> StatusCode {
+    error!("admin: {e}");
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+// a prefix or pattern the crawler can't use is rejected before it reaches the table
+fn check_scope(
+    url: &Url,
+    path_prefix: Option<&str>,
+    include: &[String],
+    exclude: &[String],
+) -> Result<(), StatusCode> {
+    Scope::new(url, path_prefix, include, exclude).map_err(|e| {
+        info!("admin: invalid scope for {url} {e}");
+        StatusCode::BAD_REQUEST
+    })?;
+    Ok(())
+}
+
+fn check_weight(weight: Option<f32>) -> Result<(), StatusCode> {
+    match weight {
+        Some(w) if !w.is_finite() || w <= 0.0 => Err(StatusCode::BAD_REQUEST),
+        _ => Ok(()),
+    }
+}
+
+// the change is in the table already, a lost announcement is picked up on the next restart
+async fn announce(state: &AdminState, host: &str) {
+    let message = ControlMessage::SiteChanged {
+        host: host.to_string(),
+    };
+    if let Err(e) = message.send(&state.control).await {
+        error!("admin: error while announcing {message:?} {e}");
+    }
+}
+
+async fn list_sites(
+    State(state): State<AdminState>,
+) -> Result<Json<Vec<SiteResponse>>, StatusCode> {
+    let sites = state.db.get_sites().await.map_err(internal_error)?;
+    Ok(Json(sites.into_iter().map(SiteResponse::from).collect()))
+}
+
+async fn add_site(
+    State(state): State<AdminState>,
+    Json(input): Json<NewSite>,
+) -> Result<(StatusCode, Json<SiteResponse>), StatusCode> {
+    let url = Url::parse(&input.url).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let host = site_key(&url).ok_or(StatusCode::BAD_REQUEST)?;
+    check_weight(input.weight)?;
+    check_scope(
+        &url,
+        input.path_prefix.as_deref(),
+        &input.include,
+        &input.exclude,
+    )?;
+
+    let row = SiteRow {
+        host,
+        url: url.to_string(),
+        depth: input.depth.map(|d| d as i32),
+        rps: input.rps.map(|r| r as i32),
+        max_body_size: input.max_body_size.map(|s| s as i64),
+        paused: false,
+        path_prefix: input.path_prefix,
+        include_patterns: input.include,
+        exclude_patterns: input.exclude,
+        domains: input.domains,
+        external_hops: input.external_hops as i32,
+        weight: input.weight.unwrap_or(1.0),
+        max_pages: input.max_pages.map(|p| p as i32),
+        max_bytes: input.max_bytes.map(|b| b as i64),
+        max_crawl_secs: input.max_crawl_secs.map(|s| s as i32),
+        budget_cycle_secs: input
+            .budget_cycle_secs
+            .map_or(DEFAULT_BUDGET_CYCLE, |s| s as i32),
+    };
+
+    let inserted = state.db.insert_site(&row).await.map_err(internal_error)?;
+    if !inserted {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    info!("admin: added site {}", row.host);
+    announce(&state, &row.host).await;
+    Ok((StatusCode::CREATED, Json(row.into())))
+}
+
+async fn update_site(
+    State(state): State<AdminState>,
+    Path(host): Path<String>,
+    Json(input): Json<SiteUpdate>,
+) -> Result<Json<SiteResponse>, StatusCode> {
+    let site = state
+        .db
+        .get_site(&host)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let url = Url::parse(&site.url).map_err(internal_error)?;
+    check_weight(input.weight)?;
+    check_scope(
+        &url,
+        match &input.path_prefix {
+            Some(path_prefix) => path_prefix.as_deref(),
+            None => site.path_prefix.as_deref(),
+        },
+        input.include.as_ref().unwrap_or(&site.include_patterns),
+        input.exclude.as_ref().unwrap_or(&site.exclude_patterns),
+    )?;
+
+    let changes = SiteChanges {
+        depth: input.depth.map(|d| d.map(|d| d as i32)),
+        rps: input.rps.map(|r| r.map(|r| r as i32)),
+        max_body_size: input.max_body_size.map(|s| s.map(|s| s as i64)),
+        path_prefix: input.path_prefix,
+        include_patterns: input.include,
+        exclude_patterns: input.exclude,
+        domains: input.domains,
+        external_hops: input.external_hops.map(|h| h as i32),
+        weight: input.weight,
+        max_pages: input.max_pages.map(|p| p.map(|p| p as i32)),
+        max_bytes: input.max_bytes.map(|b| b.map(|b| b as i64)),
+        max_crawl_secs: input.max_crawl_secs.map(|s| s.map(|s| s as i32)),
+        budget_cycle_secs: input.budget_cycle_secs.map(|s| s as i32),
+    };
+    let row = state
+        .db
+        .update_site(&host, &changes)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    info!("admin: updated site {host}");
+    announce(&state, &host).await;
+    Ok(Json(row.into()))
+}
+
+async fn remove_site(
+    State(state): State<AdminState>,
+    Path(host): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let deleted = state.db.delete_site(&host).await.map_err(internal_error)?;
+    if !deleted {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    info!("admin: removed site {host}");
+    announce(&state, &host).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn pause_site(
+    State(state): State<AdminState>,
+    Path(host): Path<String>,
+) -> Result<Json<SiteResponse>, StatusCode> {
+    set_paused(state, host, true).await
+}
+
+async fn resume_site(
+    State(state): State<AdminState>,
+    Path(host): Path<String>,
+) -> Result<Json<SiteResponse>, StatusCode> {
+    set_paused(state, host, false).await
+}
+
+async fn set_paused(
+    state: AdminState,
+    host: String,
+    paused: bool,
+) -> Result<Json<SiteResponse>, StatusCode> {
+    let row = state
+        .db
+        .set_site_paused(&host, paused)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    info!("admin: site {host} paused: {paused}");
+    announce(&state, &host).await;
+    Ok(Json(row.into()))
+}
+
+async fn list_traps(
+    State(state): State<AdminState>,
+) -> Result<Json<Vec<TrapResponse>>, StatusCode> {
+    let traps = state.db.get_traps().await.map_err(internal_error)?;
+    Ok(Json(traps.into_iter().map(TrapResponse::from).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_site_update_null() {
+        let update = serde_json::from_str::<SiteUpdate>(
+            r#"{"depth": null, "rps": 2, "max_pages": null, "weight": 2.0}"#,
+        )
+        .unwrap();
+
+        assert_eq!(update.depth, Some(None));
+        assert_eq!(update.rps, Some(Some(2)));
+        assert_eq!(update.max_pages, Some(None));
+        assert_eq!(update.max_bytes, None);
+        assert_eq!(update.path_prefix, None);
+        assert_eq!(update.weight, Some(2.0));
+        assert_eq!(update.include, None);
+    }
+}
@@ -0,0 +1,241 @@
+use chardetng::EncodingDetector;
+use encoding_rs::Encoding;
+use mime::Mime;
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use reqwest::Response;
+
+use crate::redirect::attributes;
+
+// content the parser knows how to extract text from, everything else is skipped before it reaches redis
+pub const INDEXABLE_MIME_TYPES: &[&str] = &[
+    "text/html",
+    "application/xhtml+xml",
+    "text/plain",
+    "text/markdown",
+    "text/x-markdown",
+    "application/pdf",
+];
+
+// labels servers send when they don't know the type, sniffed like a missing content-type
+const UNKNOWN_MIME_TYPES: &[&str] = &[
+    "application/octet-stream",
+    "application/unknown",
+    "unknown/unknown",
+    "*/*",
+];
+
+// <meta charset> has to appear within the first 1024 bytes
+const META_PRESCAN_SIZE: usize = 1024;
+const SNIFF_SIZE: usize = 512;
+
+pub fn is_indexable(mime: &Mime) -> bool {
+    INDEXABLE_MIME_TYPES.contains(&mime.essence_str())
+}
+
+// sent to the parser as is, not decoded to text
+pub fn is_binary(mime: &Mime) -> bool {
+    mime.essence_str() == "application/pdf"
+}
+
+pub fn needs_sniffing(mime: &Mime) -> bool {
+    UNKNOWN_MIME_TYPES.contains(&mime.essence_str())
+}
+
+// none if it is missing or useless, in which case the body is sniffed
+pub fn content_type(headers: &HeaderMap) -> Option<Mime> {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<Mime>().ok())
+        .filter(|m| !needs_sniffing(m))
+}
+
+// reads the body up to `max` bytes, none if it is larger
+pub async fn read_limited(res: Response, max: usize) -> reqwest::Result<Option<Vec<u8>>> {
+    read_counted(res, max).await.0
+}
+
+// read_limited, along with the bytes that came in before the body was complete, too large or failed
+pub async fn read_counted(
+    mut res: Response,
+    max: usize,
+) -> (reqwest::Result<Option<Vec<u8>>>, usize) {
+    if res.content_length().is_some_and(|l| l > max as u64) {
+        return (Ok(None), 0);
+    }
+
+    let mut body = vec![];
+    loop {
+        let chunk = match res.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => return (Err(e), body.len()),
+        };
+        if body.len() + chunk.len() > max {
+            return (Ok(None), body.len() + chunk.len());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let received = body.len();
+    (Ok(Some(body)), received)
+}
+
+// guesses the type of a body served without a usable content-type
+pub fn sniff(body: &[u8]) -> Mime {
+    let head = &body[..body.len().min(SNIFF_SIZE)];
+    let head = head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head);
+    let start = head
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(head.len());
+    let head = &head[start..];
+
+    let html_tags: [&[u8]; 8] = [
+        b"<!doctype html",
+        b"<html",
+        b"<head",
+        b"<body",
+        b"<title",
+        b"<script",
+        b"<div",
+        b"<p",
+    ];
+    let is_html = html_tags.iter().any(|tag| {
+        head.len() > tag.len()
+            && head[..tag.len()].eq_ignore_ascii_case(tag)
+            && matches!(head[tag.len()], b' ' | b'>' | b'\t' | b'\n' | b'\r')
+    });
+
+    if is_html || head.starts_with(b"<!--") {
+        mime::TEXT_HTML
+    } else if head.starts_with(b"<?xml") {
+        mime::TEXT_XML
+    } else if head.starts_with(b"%PDF-") {
+        mime::APPLICATION_PDF
+    } else if head
+        .iter()
+        .any(|b| matches!(b, 0x00..=0x08 | 0x0b | 0x0e..=0x1a | 0x1c..=0x1f))
+    {
+        mime::APPLICATION_OCTET_STREAM
+    } else {
+        mime::TEXT_PLAIN
+    }
+}
+
+// decodes the body to utf-8 using detect_encoding
+pub fn decode(body: &[u8], charset: Option<&str>) -> String {
+    let (text, _, _) = detect_encoding(body, charset).decode(body);
+    text.into_owned()
+}
+
+// the encoding comes from a bom, the header charset, a <meta charset>
+// or failing those a guess based on the content
+pub fn detect_encoding(body: &[u8], charset: Option<&str>) -> &'static Encoding {
+    Encoding::for_bom(body)
+        .map(|(e, _)| e)
+        .or_else(|| charset.and_then(|c| Encoding::for_label(c.trim().as_bytes())))
+        .or_else(|| meta_charset(body))
+        .unwrap_or_else(|| {
+            let mut detector = EncodingDetector::new();
+            detector.feed(body, true);
+            detector.guess(None, true)
+        })
+}
+
+// <meta charset="..."> or <meta http-equiv="content-type" content="text/html; charset=...">
+fn meta_charset(body: &[u8]) -> Option<&'static Encoding> {
+    let head = String::from_utf8_lossy(&body[..body.len().min(META_PRESCAN_SIZE)]);
+    let lower = head.to_ascii_lowercase();
+
+    let mut pos = 0;
+    while let Some(start) = lower.get(pos..).and_then(|l| l.find("<meta")) {
+        let start = pos + start + "<meta".len();
+        let Some(len) = head[start..].find('>') else {
+            break;
+        };
+        pos = start + len;
+
+        let attrs = attributes(&head[start..pos]);
+        let label = attrs
+            .iter()
+            .find(|(k, _)| k == "charset")
+            .map(|(_, v)| v.clone())
+            .or_else(|| {
+                let (_, content) = attrs.iter().find(|(k, _)| k == "content")?;
+                let content = content.to_ascii_lowercase();
+                let (_, charset) = content.split_once("charset=")?;
+                let charset = charset.trim_matches(|c: char| c == '"' || c == '\'' || c == ' ');
+                Some(charset.split(';').next().unwrap_or_default().to_string())
+            });
+
+        if let Some(encoding) = label.and_then(|l| Encoding::for_label(l.trim().as_bytes())) {
+            // a utf-16 label can't be right for a tag we just read as ascii, it means utf-8
+            return Some(encoding.output_encoding());
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        let cases: [(&[u8], Mime); 9] = [
+            (b"<!DOCTYPE html><html></html>", mime::TEXT_HTML),
+            (b"\xef\xbb\xbf\n  <html lang=\"en\">", mime::TEXT_HTML),
+            (b"<p>hello</p>", mime::TEXT_HTML),
+            (b"<!-- comment --><div>", mime::TEXT_HTML),
+            (b"<pre>not sniffed</pre>", mime::TEXT_PLAIN),
+            (b"<?xml version=\"1.0\"?><rss>", mime::TEXT_XML),
+            (b"%PDF-1.7\n", mime::APPLICATION_PDF),
+            (b"\x89PNG\r\n\x1a\n\x00\x00", mime::APPLICATION_OCTET_STREAM),
+            (b"just some text", mime::TEXT_PLAIN),
+        ];
+
+        for (body, expected) in cases {
+            assert_eq!(sniff(body), expected, "{}", String::from_utf8_lossy(body));
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        // latin-1 from the header
+        assert_eq!(decode(b"caf\xe9", Some("ISO-8859-1")), "café");
+        // the bom wins over the header
+        assert_eq!(decode(b"\xef\xbb\xbfcaf\xc3\xa9", Some("latin1")), "café");
+        // <meta charset>
+        let html = b"<html><head><meta charset=\"windows-1251\"></head><body>\xcf\xf0\xe8\xe2\xe5\xf2</body></html>";
+        assert!(decode(html, None).contains("Привет"));
+        // <meta http-equiv> with an unknown header charset
+        let html = b"<head><meta http-equiv=\"Content-Type\" content=\"text/html; charset=Shift_JIS\"></head>\x93\xfa\x96\x7b";
+        assert!(decode(html, Some("bogus")).ends_with("日本"));
+        // neither, guessed from the content
+        assert_eq!(decode("plain utf-8 ✓".as_bytes(), None), "plain utf-8 ✓");
+    }
+
+    #[test]
+    fn test_meta_charset() {
+        let cases: [(&[u8], Option<&'static Encoding>); 4] = [
+            (b"<meta charset='utf-8'>", Some(encoding_rs::UTF_8)),
+            (b"<META CHARSET=euc-kr>", Some(encoding_rs::EUC_KR)),
+            (b"<meta charset=\"utf-16le\">", Some(encoding_rs::UTF_8)),
+            (
+                b"<meta name=\"viewport\" content=\"width=device-width\">",
+                None,
+            ),
+        ];
+
+        for (body, expected) in cases {
+            assert_eq!(
+                meta_charset(body),
+                expected,
+                "{}",
+                String::from_utf8_lossy(body)
+            );
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use sqlx::Acquire;
+
+use db::{Db, SiteRow};
+
+// how much a site may crawl per cycle, shared by its hosts and kept in crawl_budgets
+// once a limit is reached its workers stop claiming urls until the cycle is over
+#[derive(Debug, Clone, PartialEq)]
+pub struct Budget {
+    max_pages: Option<u64>,
+    max_bytes: Option<u64>,
+    max_crawl_time: Option<Duration>, // time spent on requests, not on politeness
+    cycle: Duration,
+    usage: Usage, // as of the last load or charge
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Usage {
+    pages: u64,
+    bytes: u64, // bodies as received, before decoding
+    crawl_time: Duration,
+    cycle_ends: SystemTime,
+}
+
+impl Default for Usage {
+    fn default() -> Self {
+        Usage {
+            pages: 0,
+            bytes: 0,
+            crawl_time: Duration::ZERO,
+            cycle_ends: SystemTime::UNIX_EPOCH,
+        }
+    }
+}
+
+impl Budget {
+    const MIN_CYCLE: u64 = 60;
+
+    pub fn new(row: &SiteRow) -> Self {
+        Budget {
+            max_pages: row.max_pages.map(|p| p.max(0) as u64),
+            max_bytes: row.max_bytes.map(|b| b.max(0) as u64),
+            max_crawl_time: row
+                .max_crawl_secs
+                .map(|s| Duration::from_secs(s.max(0) as u64)),
+            cycle: Duration::from_secs((row.budget_cycle_secs.max(0) as u64).max(Self::MIN_CYCLE)),
+            usage: Usage::default(),
+        }
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.max_pages.is_some() || self.max_bytes.is_some() || self.max_crawl_time.is_some()
+    }
+
+    // time left in the current cycle if one of its limits is reached
+    pub fn exhausted(&self) -> Option<Duration> {
+        let remaining = self
+            .usage
+            .cycle_ends
+            .duration_since(SystemTime::now())
+            .ok()
+            .filter(|r| !r.is_zero())?;
+
+        let usage = &self.usage;
+        let reached = self.max_pages.is_some_and(|m| usage.pages >= m)
+            || self.max_bytes.is_some_and(|m| usage.bytes >= m)
+            || self.max_crawl_time.is_some_and(|m| usage.crawl_time >= m);
+
+        reached.then_some(remaining)
+    }
+
+    // picks up what other workers and instances crawled for the site
+    pub async fn load(&mut self, db: &Db, site: &str) -> Result<()> {
+        let mut pool = db.get_pg().await?;
+
+        let usage = sqlx::query_as::<_, (i64, i64, i64, f64)>(
+            r#"
+            SELECT pages, bytes, crawl_ms,
+                EXTRACT(EPOCH FROM cycle_started_at + make_interval(secs => $2) - now())::float8
+            FROM crawl_budgets
+            WHERE site = $1
+            "#,
+        )
+        .bind(site)
+        .bind(self.cycle.as_secs_f64())
+        .fetch_optional(pool.acquire().await?)
+        .await?;
+
+        self.usage = usage.map(Usage::from_row).unwrap_or_default();
+        Ok(())
+    }
+
+    // adds a request to the site's usage, a cycle that is over starts afresh with it
+    // every request costs the body bytes it downloaded and its time
+    // only the ones that returned a page count as pages
+    pub async fn charge(
+        &mut self,
+        db: &Db,
+        site: &str,
+        pages: u64,
+        bytes: usize,
+        elapsed: Duration,
+    ) -> Result<()> {
+        let mut pool = db.get_pg().await?;
+
+        let usage = sqlx::query_as::<_, (i64, i64, i64, f64)>(
+            r#"
+            INSERT INTO crawl_budgets AS b (site, pages, bytes, crawl_ms)
+                VALUES ($1, $5, $2, $3)
+                ON CONFLICT (site) DO UPDATE SET
+                    cycle_started_at = CASE WHEN b.cycle_started_at + make_interval(secs => $4) <= now()
+                        THEN now() ELSE b.cycle_started_at END,
+                    pages = CASE WHEN b.cycle_started_at + make_interval(secs => $4) <= now()
+                        THEN 0 ELSE b.pages END + EXCLUDED.pages,
+                    bytes = CASE WHEN b.cycle_started_at + make_interval(secs => $4) <= now()
+                        THEN 0 ELSE b.bytes END + EXCLUDED.bytes,
+                    crawl_ms = CASE WHEN b.cycle_started_at + make_interval(secs => $4) <= now()
+                        THEN 0 ELSE b.crawl_ms END + EXCLUDED.crawl_ms
+            RETURNING pages, bytes, crawl_ms,
+                EXTRACT(EPOCH FROM cycle_started_at + make_interval(secs => $4) - now())::float8
+            "#,
+        )
+        .bind(site)
+        .bind(bytes as i64)
+        .bind(elapsed.as_millis() as i64)
+        .bind(self.cycle.as_secs_f64())
+        .bind(pages as i64)
+        .fetch_one(pool.acquire().await?)
+        .await?;
+
+        self.usage = Usage::from_row(usage);
+        Ok(())
+    }
+}
+
+impl Usage {
+    fn from_row((pages, bytes, crawl_ms, remaining): (i64, i64, i64, f64)) -> Self {
+        Usage {
+            pages: pages.max(0) as u64,
+            bytes: bytes.max(0) as u64,
+            crawl_time: Duration::from_millis(crawl_ms.max(0) as u64),
+            cycle_ends: SystemTime::now() + Duration::from_secs_f64(remaining.max(0.0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exhausted() {
+        let budget = Budget {
+            max_pages: Some(100),
+            max_bytes: Some(1000),
+            max_crawl_time: Some(Duration::from_secs(60)),
+            cycle: Duration::from_secs(3600),
+            usage: Usage::default(),
+        };
+        let usage = |pages, bytes, secs, remaining| Usage {
+            pages,
+            bytes,
+            crawl_time: Duration::from_secs(secs),
+            cycle_ends: SystemTime::now() + Duration::from_secs(remaining),
+        };
+
+        let cases = [
+            (usage(10, 100, 10, 600), false),
+            (usage(100, 100, 10, 600), true),
+            (usage(10, 1000, 10, 600), true),
+            (usage(10, 100, 60, 600), true),
+            // the cycle is over, the next charge starts a new one
+            (usage(100, 1000, 60, 0), false),
+        ];
+
+        for (usage, expected) in cases {
+            let budget = Budget {
+                usage: usage.clone(),
+                ..budget.clone()
+            };
+            assert_eq!(budget.exhausted().is_some(), expected, "{usage:?}");
+        }
+
+        assert!(budget.is_limited());
+        let unlimited = Budget {
+            max_pages: None,
+            max_bytes: None,
+            max_crawl_time: None,
+            usage: usage(1000, 1000, 1000, 600),
+            ..budget
+        };
+        assert!(!unlimited.is_limited());
+        assert!(unlimited.exhausted().is_none());
+    }
+}
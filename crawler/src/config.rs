@@ -1,78 +1,318 @@
+use crate::budget::Budget;
 use crate::robots::RobotsTxt;
 use anyhow::{anyhow, Result};
+use db::{Db, SiteRow};
 use serde::{Deserialize, Serialize};
 use std::fs::read_to_string;
+use std::path::Path;
 use std::time::{Duration, SystemTime};
+use tracing::{error, info};
 use url::Url;
+use utils::{matches_domain, site_key, ControlMessage, RabbitMQ, Scope};
 
 const DEFAULT_RPS: f64 = 0.5;
+const DEFAULT_MAX_BODY_SIZE: usize = 5 * 1024 * 1024;
+pub const DEFAULT_BUDGET_CYCLE: i32 = 60 * 60 * 24;
 pub const FOXEYE_USER_AGENT: &str = "Foxeye Search";
 
 #[derive(Debug, Clone)]
 pub struct Sites {
     pub url: Url,
     pub depth: Option<u32>,
-    #[allow(dead_code)]
     pub rps: Option<u32>,
     pub timer: Timer,
     pub robots: RobotsTxt,
+    pub max_body_size: usize, // bytes, larger pages are skipped
+    pub paused: bool,         // links are still queued, nothing is fetched
+    pub scope: Scope,
+    pub site: String, // host of the configured site, differs for hosts discovered through it
+    pub domains: Vec<String>, // other hosts the site covers, e.g. *.rust-lang.org
+    pub external_hops: u32, // links followed off the site's hosts
+    pub budget: Budget, // pages, bytes and crawl time per cycle, shared with discovered hosts
 }
 
 impl Sites {
-    pub fn is_allowed(&self, path: &str) -> bool {
-        self.robots.is_allowed(FOXEYE_USER_AGENT, path)
+    pub async fn load(row: &SiteRow) -> Result<Sites> {
+        let mut site = Self::from_row(row)?;
+        (site.robots, site.timer) = politeness(&site.url, site.rps).await?;
+
+        if !site.robots.sitemaps().is_empty() {
+            info!(
+                "found {} sitemaps for {}",
+                site.robots.sitemaps().len(),
+                site.url
+            );
+        }
+
+        Ok(site)
+    }
+
+    // the site as configured, without fetching its robots.txt
+    // good enough for for_host, which fetches the robots.txt of the host it is called for
+    pub fn from_row(row: &SiteRow) -> Result<Sites> {
+        let url = Url::parse(&row.url)?;
+        let rps = row.rps.map(|r| r.max(1) as u32);
+        let scope = Scope::new(
+            &url,
+            row.path_prefix.as_deref(),
+            &row.include_patterns,
+            &row.exclude_patterns,
+        )?;
+
+        Ok(Sites {
+            url,
+            depth: row.depth.map(|d| d.max(0) as u32),
+            rps,
+            timer: Timer::new(request_interval(rps)),
+            robots: RobotsTxt::default(),
+            max_body_size: row
+                .max_body_size
+                .map_or(DEFAULT_MAX_BODY_SIZE, |s| s.max(0) as usize),
+            paused: row.paused,
+            scope,
+            site: row.host.clone(),
+            domains: row.domains.clone(),
+            external_hops: row.external_hops.max(0) as u32,
+            budget: Budget::new(row),
+        })
+    }
+
+    // config of a host first seen in crawler_queue, crawled on behalf of this site
+    // with its own robots.txt and timer, external hosts are crawled without the site's patterns
+    pub async fn for_host(&self, host: &str) -> Result<Sites> {
+        let url = Url::parse(&format!("{}://{host}/", self.url.scheme()))?;
+        let (robots, timer) = politeness(&url, self.rps).await?;
+
+        let scope = if self.covers(host) {
+            self.scope.for_other_host()
+        } else {
+            Scope::new(&url, Some("/"), &[], &[])?
+        };
+
+        Ok(Sites {
+            url,
+            timer,
+            robots,
+            scope,
+            ..self.clone()
+        })
+    }
+
+    // the site's own host or one of its domains
+    pub fn covers(&self, host: &str) -> bool {
+        self.url.host_str() == Some(host) || self.domains.iter().any(|d| matches_domain(host, d))
+    }
+
+    pub fn is_configured(&self) -> bool {
+        site_key(&self.url).as_deref() == Some(self.site.as_str())
+    }
+
+    // a file:// site, its directory is walked instead of crawled
+    pub fn is_local(&self) -> bool {
+        self.url.scheme() == "file"
+    }
+
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        self.robots.is_allowed(FOXEYE_USER_AGENT, &robots_path(url))
+    }
+
+    // the robots.txt rule that decides the url, none if no rule matches it
+    pub fn robots_rule(&self, url: &Url) -> Option<String> {
+        self.robots
+            .matching_rule(FOXEYE_USER_AGENT, &robots_path(url))
     }
 }
 
+// robots.txt rules match against the path and query
+fn robots_path(url: &Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    }
+}
+
+fn request_interval(rps: Option<u32>) -> Duration {
+    match rps {
+        Some(rps) => Duration::from_secs_f64(1f64 / rps as f64),
+        None => Duration::from_secs_f64(DEFAULT_RPS),
+    }
+}
+
+// fetches robots.txt of the host, its crawl-delay overrides rps when it is stricter
+async fn politeness(url: &Url, rps: Option<u32>) -> Result<(RobotsTxt, Timer)> {
+    let mut t = request_interval(rps).as_secs_f64();
+
+    let host = url.host();
+
+    let mut robots = RobotsTxt::default();
+
+    if let Some(h) = host {
+        if url.scheme().starts_with("http") {
+            let h = format!("{}://{}", url.scheme(), h);
+
+            robots = RobotsTxt::from_url(Url::parse(&h)?).await?
+        }
+    }
+
+    if let Some(delay) = robots.crawl_delay(FOXEYE_USER_AGENT) {
+        if delay.as_secs_f64() > t {
+            info!("using crawl-delay of {}s for {url}", delay.as_secs_f64());
+            t = delay.as_secs_f64();
+        }
+    }
+
+    Ok((robots, Timer::new(Duration::from_secs_f64(t))))
+}
+
+// an entry of sites.json, the file seeds the sites table which is the source of truth
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SitesConfig {
-    url: String,
+    url: String, // file:///some/dir/ indexes the files under a local directory
     depth: Option<u32>,
     rps: Option<u32>, // request per second
+    max_body_size: Option<usize>,
+    path_prefix: Option<String>,
+    #[serde(default)]
+    include: Vec<String>, // globs, or regexes prefixed with "re:"
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default)]
+    domains: Vec<String>, // e.g. "*.rust-lang.org"
+    #[serde(default)]
+    external_hops: u32,
+    weight: Option<f32>, // crawl priority of the site's urls relative to other sites, 1 by default
+    max_pages: Option<u32>, // per budget cycle
+    max_bytes: Option<u64>,
+    max_crawl_secs: Option<u32>,
+    budget_cycle_secs: Option<u32>, // 1 day by default
 }
 
 impl SitesConfig {
-    pub async fn load_config() -> Result<Vec<Sites>> {
-        let sites = read_to_string("sites.json")?;
+    const CONFIG_FILE: &'static str = "sites.json";
+    const WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+    // none if there is no sites.json, sites are then managed through the admin api only
+    fn read_file() -> Result<Option<Vec<SiteRow>>> {
+        if !Path::new(Self::CONFIG_FILE).exists() {
+            return Ok(None);
+        }
+
+        let sites = read_to_string(Self::CONFIG_FILE)?;
         let val = serde_json::from_str::<serde_json::Value>(&sites)?;
         let val = val
             .get("sites")
             .ok_or(anyhow!("no field \"sites\" found in config"))?;
         let val = serde_json::from_value::<Vec<SitesConfig>>(val.to_owned())?;
 
-        let mut sites = vec![];
-
+        let mut rows = vec![];
         for v in val {
             let url = Url::parse(&v.url)?;
+            let host = site_key(&url).ok_or(anyhow!("no host found in site url {url}"))?;
 
-            let t = if v.rps.is_some() {
-                1f64 / v.rps.unwrap() as f64
-            } else {
-                DEFAULT_RPS
-            };
+            rows.push(SiteRow {
+                host,
+                url: url.to_string(),
+                depth: v.depth.map(|d| d as i32),
+                rps: v.rps.map(|r| r as i32),
+                max_body_size: v.max_body_size.map(|s| s as i64),
+                paused: false,
+                path_prefix: v.path_prefix,
+                include_patterns: v.include,
+                exclude_patterns: v.exclude,
+                domains: v.domains,
+                external_hops: v.external_hops as i32,
+                weight: v.weight.unwrap_or(1.0),
+                max_pages: v.max_pages.map(|p| p as i32),
+                max_bytes: v.max_bytes.map(|b| b as i64),
+                max_crawl_secs: v.max_crawl_secs.map(|s| s as i32),
+                budget_cycle_secs: v
+                    .budget_cycle_secs
+                    .map_or(DEFAULT_BUDGET_CYCLE, |s| s as i32),
+            });
+        }
+
+        Ok(Some(rows))
+    }
+
+    // seeds the sites table with the sites of sites.json it doesn't have yet
+    // existing sites are left alone, they may have been changed or deleted through the admin api
+    pub async fn import(db: &Db) -> Result<Vec<String>> {
+        let Some(rows) = Self::read_file()? else {
+            return Ok(vec![]);
+        };
+        Ok(db.import_sites(&rows, false).await?)
+    }
+
+    pub async fn load_config(db: &Db) -> Result<Vec<Sites>> {
+        Self::import(db).await?;
+
+        let mut sites = vec![];
+        for row in db.get_sites().await? {
+            sites.push(Sites::load(&row).await?);
+        }
+
+        Ok(sites)
+    }
 
-            let host = url.host();
+    // applies the sites edited in sites.json whenever it is modified and announces the sites that changed
+    // sites whose entry is the same as before keep what the admin api did to them
+    pub async fn watch_file(db: Db, control: RabbitMQ) {
+        let modified = || {
+            std::fs::metadata(Self::CONFIG_FILE)
+                .and_then(|m| m.modified())
+                .ok()
+        };
+        let mut last = modified();
+        let mut last_rows = Self::read_file().ok().flatten().unwrap_or_default();
 
-            let mut robots = RobotsTxt::default();
+        loop {
+            tokio::time::sleep(Self::WATCH_INTERVAL).await;
 
-            if let Some(h) = host {
-                if url.scheme().starts_with("http") {
-                    let h = format!("{}://{}", url.scheme(), h);
+            let current = modified();
+            if current.is_none() || current == last {
+                continue;
+            }
+            last = current;
 
-                    robots = RobotsTxt::from_url(Url::parse(&h)?).await?
+            let rows = match Self::read_file() {
+                Ok(rows) => rows.unwrap_or_default(),
+                Err(e) => {
+                    error!("watch_file: error while reading {} {e}", Self::CONFIG_FILE);
+                    continue;
                 }
+            };
+            let edited = rows
+                .iter()
+                .filter(|row| !last_rows.contains(row))
+                .cloned()
+                .collect::<Vec<_>>();
+            if edited.is_empty() {
+                continue;
             }
 
-            sites.push(Sites {
-                url,
-                depth: v.depth,
-                rps: v.rps,
-                timer: Timer::new(Duration::from_secs_f64(t)),
-                robots,
-            })
-        }
+            info!(
+                "watch_file: {} changed, importing {} sites",
+                Self::CONFIG_FILE,
+                edited.len()
+            );
+            let changed = match db.import_sites(&edited, true).await {
+                Ok(changed) => {
+                    last_rows = rows;
+                    changed
+                }
+                Err(e) => {
+                    error!("watch_file: error while importing sites {e}");
+                    continue;
+                }
+            };
 
-        Ok(sites)
+            for host in changed {
+                let message = ControlMessage::SiteChanged { host };
+                if let Err(e) = message.send(&control).await {
+                    error!("watch_file: error while announcing {message:?} {e}");
+                }
+            }
+        }
     }
 }
 
@@ -90,11 +330,25 @@ impl Timer {
         }
     }
 
-    pub fn can_send(&mut self) -> bool {
-        if SystemTime::now() > (self.start_time + self.time_between) {
-            self.start_time = SystemTime::now();
-            return true;
-        }
-        false
+    pub fn interval(&self) -> Duration {
+        self.time_between
+    }
+
+    // time left until the next request is allowed
+    pub fn remaining(&self) -> Duration {
+        (self.start_time + self.time_between)
+            .duration_since(SystemTime::now())
+            .unwrap_or_default()
+    }
+
+    // no request before `d` has passed, on top of the usual interval
+    pub fn delay(&mut self, d: Duration) {
+        self.start_time = self.start_time.max(SystemTime::now() + d);
+    }
+
+    // waits until the next request is allowed and marks the slot as used
+    pub async fn wait(&mut self) {
+        tokio::time::sleep(self.remaining()).await;
+        self.start_time = SystemTime::now();
     }
 }
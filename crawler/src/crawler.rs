@@ -1,50 +1,110 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::env;
-use std::time::Duration;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
+use base64::prelude::*;
 use mime::Mime;
-use reqwest::header::{CONTENT_TYPE, USER_AGENT};
-use reqwest::Client;
+use reqwest::header::{
+    HeaderMap, HeaderValue, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+    USER_AGENT,
+};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Response, StatusCode};
+use sha2::{Digest, Sha256};
 use sqlx::Acquire;
+use time::format_description::well_known::Rfc3339;
+use time::{OffsetDateTime, PrimitiveDateTime};
+use tokio::sync::mpsc::{self, unbounded_channel};
+use tokio::sync::{watch, Semaphore};
+use tokio::task::{spawn_blocking, AbortHandle, JoinSet};
 use tracing::{error, info, warn};
 use ulid::Ulid;
 use url::Url;
 
 use db::Db;
 
+use crate::body::{content_type, decode, is_binary, is_indexable, read_counted, sniff};
 use crate::config::{Sites, SitesConfig, FOXEYE_USER_AGENT};
-use utils::{CrawlMessage, CrawlUrl, RabbitMQ};
+use crate::local::{walk, LocalFile};
+use crate::outcome::{Crawled, FetchOutcome};
+use crate::redirect::{meta_refresh, MAX_REDIRECTS};
+use crate::refresh::CrawlState;
+use crate::sitemap::{ChangeFreq, Sitemap, SitemapUrl};
+use crate::warc::{
+    decode_payload, parse_response, RequestHead, ResponseHead, WarcArchive, WarcReader, WarcRecord,
+};
+use utils::{detect_trap, Canonicalizer, ControlMessage, CrawlMessage, CrawlUrl, RabbitMQ};
 
 #[derive(Debug, Clone)]
 pub struct Crawler {
     client: Client,
     db: Db,
-    site_map: HashMap<String, Sites>, // host url -> site config
-    url_queue: Vec<CrawlUrl>,         // url queue
+    site_map: Arc<RwLock<HashMap<String, Sites>>>, // host url -> site config, reloaded on control messages
     amq: RabbitMQ,
+    permits: Arc<Semaphore>, // global cap on in-flight requests across all hosts
+    worker_id: String,       // identifies this instance's leases in crawler_queue
+    canonicalizer: Canonicalizer,
+    stop: Arc<watch::Sender<bool>>, // set on shutdown, workers return their urls and exit
+    warc: Option<WarcArchive>,      // archive of every response, when WARC_DIR is set
 }
 
 impl Crawler {
     const MAX_QUEUE_SIZE: usize = 100;
+    // claimed urls not crawled within this time become claimable by other instances
+    const LEASE_DURATION: Duration = Duration::from_secs(60 * 10);
+    const MAX_IN_FLIGHT: usize = 16;
+    // a host whose timer is further away than this hands its queue back to the database
+    const MAX_TIMER_WAIT: Duration = Duration::from_secs(30);
+    const MAX_ATTEMPTS: u32 = 5;
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+    const BASE_BACKOFF: Duration = Duration::from_secs(60);
+    const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60 * 24);
+    const SITEMAP_BATCH_SIZE: usize = 1000;
+    const DISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
+    // workers get this long to finish the url they're on before they are aborted
+    const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+    // archived records read ahead of the ones being imported
+    const IMPORT_BUFFER: usize = 64;
+    // file:// sites are walked again after this long
+    const LOCAL_SCAN_INTERVAL: Duration = Duration::from_secs(60 * 5);
     const _MAX_DEPTH: u32 = 10;
     pub async fn new() -> Result<Crawler> {
-        let config = SitesConfig::load_config().await?;
+        let db = Db::new(5).await?;
+        let config = SitesConfig::load_config(&db).await?;
         info!("sites loaded: {}", config.len());
 
         let mut site_map = HashMap::new();
-        let mut url_queue = vec![];
 
+        // keyed on the site, the host of configured sites or the url of file:// sites
         for site in config {
-            if let Some(host) = site.url.host() {
-                let crl = CrawlUrl {
-                    url: site.url.clone(),
-                    depth: 0,
-                };
-                url_queue.push(crl);
-                site_map.insert(host.to_string(), site);
-            }
+            site_map.insert(site.site.clone(), site);
         }
+
+        let worker_id = env::var("CRAWLER_ID").unwrap_or_else(|_| Ulid::new().to_string());
+        info!("crawler worker id: {worker_id}");
+        let warc = WarcArchive::from_env(&worker_id)?;
+
+        Self::with(db, site_map, worker_id, warc).await
+    }
+
+    // what `crawler import` needs, sites.json isn't imported and nothing is archived
+    // sites are read from the sites table as their hosts come up, robots.txt is fetched for those hosts only
+    pub async fn importer() -> Result<Crawler> {
+        let db = Db::new(2).await?;
+        Self::with(db, HashMap::new(), "import".to_string(), None).await
+    }
+
+    async fn with(
+        db: Db,
+        site_map: HashMap<String, Sites>,
+        worker_id: String,
+        warc: Option<WarcArchive>,
+    ) -> Result<Crawler> {
         let amq_uri = env::var("RABBITMQ").map_err(|_| anyhow!("RABBITMQ env not set"))?;
 
         let amq = RabbitMQ::new(
@@ -57,140 +117,806 @@ impl Crawler {
         .await?;
 
         Ok(Crawler {
-            client: Client::new(),
-            db: Db::new(5).await?,
-            site_map,
-            url_queue,
+            client: Self::client()?,
+            db,
+            site_map: Arc::new(RwLock::new(site_map)),
             amq,
+            permits: Arc::new(Semaphore::new(Self::MAX_IN_FLIGHT)),
+            worker_id,
+            canonicalizer: Canonicalizer::load_config()?,
+            stop: Arc::new(watch::channel(false).0),
+            warc,
         })
     }
 
+    // redirects are followed in crawl to record them
+    pub fn client() -> Result<Client> {
+        Ok(Client::builder()
+            .redirect(Policy::none())
+            .connect_timeout(Self::CONNECT_TIMEOUT)
+            .timeout(Self::REQUEST_TIMEOUT)
+            .build()?)
+    }
+
     // crawling strategy
-    // check if self.url_queue is empty (max 100 urls are allowed at once)
-    // if its empty get url from db url queue using DELETE FROM url RETURNING * LIMIT 100;
+    // sites live in the sites table, sites.json seeds it with new sites on start and its edited entries are applied
+    // every configured host that isn't paused gets its own worker task with its own politeness timer
+    // a site changed through the admin api restarts its worker, new sites get their sitemaps seeded first
+    // other hosts queued for a site, matching its domains or up to its external hops away, get a worker
+    // with their own robots.txt and timer
+    // a worker keeps a local queue of at most 100 urls of its host, fewer if they can't be crawled within the lease
+    // if its empty lease due urls (next_fetch_at <= now) that nobody else holds using SELECT ... FOR UPDATE SKIP LOCKED
+    // highest priority first, the score is kept by the database from depth, inlinks, sitemap priority and site weight
+    // if the host timer is far away put the local queue back into the db with a later next_fetch_at
+    // a site that used up its pages, bytes or crawl time budget for the cycle claims no urls until the next one
     // check if url host is in self.site_map which is a hashmap of configured site to be crawled
     // check if urls is allowed according to robots.txt
-    // check timer if rate limit has passed
     // check if url depth has reached
-    // check if url is in redis if yes skip
-    // else send request to url and get response
-    // save url in redis cache for 7 days
+    // check if url is within the site's path prefix and include / exclude patterns
+    // check if url looks like a crawl trap, rejections are counted per host in crawl_traps
+    // check if canonical url is in redis if yes skip
+    // wait for the host timer, then for a global request permit
+    // else send request to url and stream the response, up to the site's max body size
+    // skip mime types we can't index, sniff the body if there is no content type
+    // decode the body to utf-8 using the header charset, <meta charset> or a guess
+    // follow redirects and meta refreshes on the same host, record the urls they came from as aliases
+    // redirects to other hosts are queued for that host's worker
+    // if the request fails with a timeout, network error, 408, 429 or 5xx reschedule the url with exponential backoff
+    // or the server's retry-after, and release the lease
+    // other 4xx and urls that keep failing are moved to crawl_errors, documents answering 404 / 410 are tombstoned
+    // send etag / last-modified of the previous crawl, 304 or an unchanged body skips parser and embedder
+    // re-crawl interval halves when the page changed and doubles when it didn't
+    // it starts at the sitemap's changefreq and stays within 4x of it
+    // sitemap urls whose lastmod isn't newer than our last crawl wait for their re-crawl, newer ones are due now
+    // crawled urls are rescheduled in db url queue for their next re-crawl, others are deleted
+    // save url in redis cache until its next re-crawl
     // get all urls from the page
     // parse url and append host to them if they start from "/"
     // save parse url in db url queue
     // save response content in redis and assign a key
     // send key to parser using rabbitmq
+    // with WARC_DIR set every response is also archived with its request in rotating gzip WARC files
+    // on SIGTERM / SIGINT workers finish the url they're on, put their local queue back and leases are released
+    // file:// sites get a worker that walks their directory instead, files with a new mtime are compared by hash
+    // and sent to the parser like crawled pages, files that disappeared are tombstoned
+    // `crawler explain <url>` runs the checks below for a single url and prints which one stops it, writing nothing
+    // `crawler import [--requeue] <files>` feeds archived WARC responses through the same checks instead of fetching
+    // the imported pages are only queued for live re-crawls with --requeue
 
-    async fn populate_urls(&mut self) -> Result<()> {
-        if self.url_queue.len() >= Self::MAX_QUEUE_SIZE {
-            return Ok(());
-        }
-
-        let stmt = format!(
-            r#"
-            DELETE FROM crawler_queue
-            WHERE url_id IN (
-            SELECT url_id
-            FROM crawler_queue
-            WHERE host = $1
-            ORDER BY created_at ASC
-            LIMIT {} )
-            RETURNING url, depth
-        "#,
-            Self::MAX_QUEUE_SIZE
-        );
+    async fn populate_urls(&self, host: &str, limit: usize) -> Result<VecDeque<CrawlUrl>> {
+        let stmt = r#"
+            WITH claimable AS (
+                SELECT url_id
+                FROM crawler_queue
+                WHERE host = $1
+                AND next_fetch_at <= now()
+                AND (lease_expires_at IS NULL OR lease_expires_at < now())
+                ORDER BY priority DESC, lastmod DESC NULLS LAST, created_at ASC
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            ), claimed AS (
+                UPDATE crawler_queue AS q
+                SET leased_by = $3, lease_expires_at = now() + make_interval(secs => $4)
+                FROM claimable
+                WHERE q.url_id = claimable.url_id
+                RETURNING q.url, q.depth, q.attempts, q.hops, q.changefreq, q.priority, q.lastmod, q.created_at
+            )
+            SELECT url, depth, attempts, hops, changefreq
+            FROM claimed
+            ORDER BY priority DESC, lastmod DESC NULLS LAST, created_at ASC
+        "#;
 
         let mut pool = self.db.get_pg().await?;
 
-        let mut crawl_urls = vec![];
-
-        for host in self.site_map.keys() {
-            let urls = sqlx::query_as::<_, (String, i32)>(&stmt)
-                .bind(host.to_owned())
-                .fetch_all(pool.acquire().await?)
-                .await?;
+        let urls = sqlx::query_as::<_, (String, i32, i32, i32, Option<String>)>(stmt)
+            .bind(host.to_owned())
+            .bind(limit as i64)
+            .bind(&self.worker_id)
+            .bind(Self::LEASE_DURATION.as_secs_f64())
+            .fetch_all(pool.acquire().await?)
+            .await?;
 
-            let urls = urls.iter().filter_map(|(url, depth)| {
+        let crawl_urls = urls
+            .iter()
+            .filter_map(|(url, depth, attempts, hops, changefreq)| {
                 if let Ok(url) = Url::parse(url) {
                     return Some(CrawlUrl {
                         url,
                         depth: *depth as u32,
+                        attempts: *attempts as u32,
+                        hops: *hops as u32,
+                        refresh_hint: changefreq
+                            .as_deref()
+                            .and_then(ChangeFreq::parse)
+                            .map(|c| c.interval()),
                     });
                 }
 
                 None
-            });
-            crawl_urls.extend(urls);
-        }
+            })
+            .collect::<VecDeque<_>>();
+
         if !crawl_urls.is_empty() {
-            info!("populated queue with {} urls", crawl_urls.len());
-            self.url_queue.extend(crawl_urls);
+            info!("populated {host} queue with {} urls", crawl_urls.len());
         } else {
-            warn!("no urls found in database");
+            warn!("no urls found in database for {host}");
+        }
+
+        Ok(crawl_urls)
+    }
+
+    // seed crawler_queue with urls from sitemaps listed in robots.txt, or /sitemap.xml if there are none
+    pub async fn seed_sitemaps(&self) -> Result<()> {
+        for (host, site) in self.sites() {
+            if !site.is_local() {
+                self.seed_site(&host, &site).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn seed_site(&self, host: &str, site: &Sites) -> Result<()> {
+        // unlike pages, sitemap redirects are of no interest
+        let client = Client::new();
+
+        let mut sitemaps = site.robots.sitemaps().to_vec();
+        if sitemaps.is_empty() && site.url.scheme().starts_with("http") {
+            sitemaps.push(site.url.join("/sitemap.xml")?);
+        }
+
+        let mut entries = vec![];
+        for sitemap in sitemaps {
+            entries.extend(Sitemap::fetch(&client, sitemap).await?);
+        }
+
+        // sitemaps can list other hosts, only keep what we are allowed to crawl
+        entries.retain(|e| {
+            e.url.host_str() == Some(host) && site.scope.allows(&e.url) && site.is_allowed(&e.url)
+        });
+
+        // variants of a url collapse into one entry, the first one listed wins
+        let mut seen = HashSet::new();
+        for e in entries.iter_mut() {
+            e.url = self.canonicalizer.normalize(&e.url);
+        }
+        entries.retain(|e| seen.insert(self.canonicalizer.canonicalize(&e.url)));
+
+        if entries.is_empty() {
+            info!("seed_sitemaps: no sitemap urls found for {host}");
+            return Ok(());
+        }
+
+        for chunk in entries.chunks(Self::SITEMAP_BATCH_SIZE) {
+            self.save_sitemap_urls(host, chunk).await?;
+        }
+        info!("seed_sitemaps: queued {} urls for {host}", entries.len());
+
+        Ok(())
+    }
+
+    // snapshot of the configured sites, the lock is never held across an await
+    fn sites(&self) -> Vec<(String, Sites)> {
+        let site_map = self.site_map.read().unwrap();
+        site_map
+            .iter()
+            .map(|(host, site)| (host.clone(), site.clone()))
+            .collect()
+    }
+
+    async fn save_sitemap_urls(&self, host: &str, entries: &[SitemapUrl]) -> Result<()> {
+        let urls = entries
+            .iter()
+            .map(|e| e.url.to_string())
+            .collect::<Vec<_>>();
+        let hosts = vec![host.to_string(); entries.len()];
+        let depths = vec![0; entries.len()];
+        let priorities = entries.iter().map(|e| e.priority).collect::<Vec<_>>();
+        let lastmods = entries.iter().map(|e| e.lastmod).collect::<Vec<_>>();
+        let changefreqs = entries
+            .iter()
+            .map(|e| e.changefreq.map(|c| c.as_str().to_string()))
+            .collect::<Vec<_>>();
+        // crawl_state and redis know the pages by their canonical url
+        let keys = entries
+            .iter()
+            .map(|e| self.canonicalizer.canonicalize(&e.url).to_string())
+            .collect::<Vec<_>>();
+
+        let mut pool = self.db.get_pg().await?;
+
+        let crawled = sqlx::query_as::<_, (String, PrimitiveDateTime)>(
+            r#"
+            SELECT url, last_crawled_at
+            FROM crawl_state
+            WHERE url = ANY($1) AND last_crawled_at IS NOT NULL
+            "#,
+        )
+        .bind(&keys)
+        .fetch_all(pool.acquire().await?)
+        .await?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+        // pages the sitemap says are unchanged since we crawled them wait for their re-crawl
+        // the others are due now, even if their re-crawl is still cached
+        let mut modified = vec![];
+        for (e, key) in entries.iter().zip(&keys) {
+            let crawled_at = crawled.get(key).copied();
+            let is_modified = e.modified_since(crawled_at);
+            if is_modified && crawled_at.is_some() {
+                self.db.del_cache(key).await?;
+            }
+            modified.push(is_modified);
         }
 
+        sqlx::query(
+            r#"
+            INSERT INTO crawler_queue (url, host, site, depth, sitemap_priority, lastmod, changefreq, next_fetch_at)
+                SELECT t.url, t.host, t.host, t.depth, t.sitemap_priority, t.lastmod, t.changefreq,
+                    CASE WHEN t.modified THEN now()
+                    ELSE COALESCE(s.last_crawled_at + make_interval(secs => s.refresh_interval), now())
+                    END
+                FROM UNNEST($1::text[], $2::text[], $3::int[], $4::real[], $5::timestamp[], $6::text[], $7::bool[], $8::text[])
+                    AS t(url, host, depth, sitemap_priority, lastmod, changefreq, modified, key)
+                LEFT JOIN crawl_state s ON s.url = t.key
+                ON CONFLICT (url) DO UPDATE SET
+                    depth = 0,
+                    sitemap_priority = EXCLUDED.sitemap_priority,
+                    lastmod = EXCLUDED.lastmod,
+                    changefreq = EXCLUDED.changefreq,
+                    next_fetch_at = LEAST(crawler_queue.next_fetch_at, EXCLUDED.next_fetch_at)
+            "#,
+        )
+        .bind(&urls)
+        .bind(&hosts)
+        .bind(&depths)
+        .bind(&priorities)
+        .bind(&lastmods)
+        .bind(&changefreqs)
+        .bind(&modified)
+        .bind(&keys)
+        .execute(pool.acquire().await?)
+        .await?;
+
         Ok(())
     }
 
-    pub async fn crawl_loop(&mut self) {
+    // runs until `shutdown` resolves, then stops the workers and hands their urls back to crawler_queue
+    // `control` carries the site changes announced by the admin api
+    pub async fn crawl_loop(&self, control: &RabbitMQ, shutdown: impl Future<Output = ()>) {
+        tokio::pin!(shutdown);
+        let mut workers = JoinSet::new();
+        let mut handles = HashMap::new();
+
+        for (host, site) in self.sites() {
+            if site.paused {
+                info!("crawl_loop: {host} is paused");
+                continue;
+            }
+            let handle = self.spawn_worker(&mut workers, &host, site, false);
+            handles.insert(host, handle);
+        }
+
+        let (tx, mut rx) = unbounded_channel();
+        if let Err(e) = control.basic_consume(&control.consumer_tag, true, tx).await {
+            error!("crawl_loop: error while consuming control messages {e}");
+        }
+
+        let mut discovery = tokio::time::interval(Self::DISCOVERY_INTERVAL);
+
         loop {
-            if self.url_queue.is_empty() {
-                info!("crawl_loop: url queue is empty, trying to populate");
-                if let Err(e) = self.populate_urls().await {
-                    error!("crawl: error while populating urls {}", e)
+            tokio::select! {
+                _ = discovery.tick() => {
+                    if let Err(e) = self.discover_hosts(&mut workers, &mut handles).await {
+                        error!("crawl_loop: error while discovering hosts {e}");
+                    }
+                }
+                Some(res) = workers.join_next(), if !workers.is_empty() => {
+                    if let Err(e) = res {
+                        // stopped by reload_site
+                        if !e.is_cancelled() {
+                            error!("crawl_loop: host worker stopped unexpectedly {e}");
+                        }
+                    }
+                }
+                Some(message) = rx.recv() => {
+                    match serde_json::from_str::<ControlMessage>(&message) {
+                        Ok(ControlMessage::SiteChanged { host }) => {
+                            if let Err(e) = self.reload_site(&host, &mut workers, &mut handles).await {
+                                error!("crawl_loop: error while reloading site {host} {e}");
+                            }
+                        }
+                        Err(e) => error!("crawl_loop: invalid control message {message} {e}"),
+                    }
+                }
+                _ = &mut shutdown => break,
+            }
+        }
+
+        self.shutdown(workers).await;
+    }
+
+    // lets the workers finish the url they're on, aborts the ones that take too long
+    // and releases whatever this instance still holds a lease on
+    async fn shutdown(&self, mut workers: JoinSet<()>) {
+        info!("shutdown: stopping {} host workers", workers.len());
+        self.stop.send_replace(true);
+
+        let drain = async { while workers.join_next().await.is_some() {} };
+        if tokio::time::timeout(Self::SHUTDOWN_TIMEOUT, drain)
+            .await
+            .is_err()
+        {
+            warn!("shutdown: aborting {} host workers", workers.len());
+            workers.abort_all();
+            while workers.join_next().await.is_some() {}
+        }
+
+        match self.release_all().await {
+            Ok(released) => info!("shutdown: released {released} urls"),
+            Err(e) => error!("shutdown: error while releasing urls {e}"),
+        }
+
+        if let Some(warc) = &self.warc {
+            if let Err(e) = warc.close().await {
+                error!("shutdown: error while closing WARC file {e}");
+            }
+        }
+    }
+
+    // hosts queued on behalf of a site, through its domains or external links, get a worker of their own
+    async fn discover_hosts(
+        &self,
+        workers: &mut JoinSet<()>,
+        handles: &mut HashMap<String, AbortHandle>,
+    ) -> Result<()> {
+        let mut pool = self.db.get_pg().await?;
+
+        let hosts = sqlx::query_as::<_, (String, String)>(
+            r#"
+            SELECT DISTINCT host, site FROM crawler_queue
+            WHERE site IS NOT NULL AND host <> site AND next_fetch_at <= now()
+            "#,
+        )
+        .fetch_all(pool.acquire().await?)
+        .await?;
+
+        for (host, site) in hosts {
+            let parent = {
+                let site_map = self.site_map.read().unwrap();
+                if site_map.contains_key(&host) {
+                    continue;
+                }
+                site_map
+                    .get(&site)
+                    .filter(|s| s.is_configured() && !s.paused)
+                    .cloned()
+            };
+            let Some(parent) = parent else {
+                continue;
+            };
+
+            // its own robots.txt and politeness timer
+            let discovered = match parent.for_host(&host).await {
+                Ok(discovered) => discovered,
+                Err(e) => {
+                    warn!("discover_hosts: can't crawl {host} for {site} {e}");
+                    continue;
                 }
+            };
+
+            info!("discover_hosts: crawling {host} for {site}");
+            self.site_map
+                .write()
+                .unwrap()
+                .insert(host.clone(), discovered.clone());
+            let handle = self.spawn_worker(workers, &host, discovered, false);
+            handles.insert(host, handle);
+        }
+
+        Ok(())
+    }
+
+    fn spawn_worker(
+        &self,
+        workers: &mut JoinSet<()>,
+        host: &str,
+        site: Sites,
+        seed: bool,
+    ) -> AbortHandle {
+        let crawler = self.clone();
+        let host = host.to_string();
+
+        workers.spawn(async move {
+            if site.is_local() {
+                return crawler.local_loop(host, site).await;
             }
-            let mut index = 0;
-            while index < self.url_queue.len() {
-                let crl = self.url_queue.get(index).unwrap().clone();
-                let url_str = crl.url.to_string();
+            if seed {
+                if let Err(e) = crawler.seed_site(&host, &site).await {
+                    error!("error while seeding urls from sitemaps of {host} {e}");
+                }
+            }
+            crawler.host_loop(host, site).await
+        })
+    }
 
+    // picks up the current row of a site, its worker is restarted with the new config
+    async fn reload_site(
+        &self,
+        host: &str,
+        workers: &mut JoinSet<()>,
+        handles: &mut HashMap<String, AbortHandle>,
+    ) -> Result<()> {
+        // hosts discovered through the site are discovered again with its new config
+        let discovered = {
+            let site_map = self.site_map.read().unwrap();
+            site_map
+                .iter()
+                .filter(|(_, s)| s.site == host && !s.is_configured())
+                .map(|(h, _)| h.clone())
+                .collect::<Vec<_>>()
+        };
+        for h in discovered.iter().map(String::as_str).chain([host]) {
+            if let Some(handle) = handles.remove(h) {
+                handle.abort();
+                self.release(h).await?;
+            }
+        }
+        {
+            let mut site_map = self.site_map.write().unwrap();
+            for h in discovered {
+                site_map.remove(&h);
+            }
+        }
+
+        let Some(row) = self.db.get_site(host).await? else {
+            self.site_map.write().unwrap().remove(host);
+            info!("reload_site: {host} removed");
+            return Ok(());
+        };
+
+        // fetches robots.txt again, it may have changed too
+        let site = Sites::load(&row).await?;
+        let is_new = !self
+            .site_map
+            .write()
+            .unwrap()
+            .insert(host.to_string(), site.clone())
+            .is_some_and(|s| s.is_configured());
+
+        if site.paused {
+            info!("reload_site: {host} paused");
+            return Ok(());
+        }
+
+        info!("reload_site: (re)starting worker for {host}");
+        let handle = self.spawn_worker(workers, host, site, is_new);
+        handles.insert(host.to_string(), handle);
+
+        Ok(())
+    }
+
+    // every lease of this instance, returns how many urls were released
+    async fn release_all(&self) -> Result<u64> {
+        let mut pool = self.db.get_pg().await?;
+
+        let res = sqlx::query(
+            r#"
+            UPDATE crawler_queue SET leased_by = NULL, lease_expires_at = NULL
+            WHERE leased_by = $1
+            "#,
+        )
+        .bind(&self.worker_id)
+        .execute(pool.acquire().await?)
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    // leases of a stopped worker, the urls are claimable again right away
+    async fn release(&self, host: &str) -> Result<()> {
+        let mut pool = self.db.get_pg().await?;
+
+        sqlx::query(
+            r#"
+            UPDATE crawler_queue SET leased_by = NULL, lease_expires_at = NULL
+            WHERE host = $1 AND leased_by = $2
+            "#,
+        )
+        .bind(host)
+        .bind(&self.worker_id)
+        .execute(pool.acquire().await?)
+        .await?;
+
+        Ok(())
+    }
+
+    // worker for a single host, owns the host's politeness timer so one slow host never stalls another
+    async fn host_loop(&self, host: String, mut site: Sites) {
+        info!("host_loop: starting worker for {host}");
+        let mut url_queue = VecDeque::new();
+        // discovered hosts only crawl what was linked to
+        if site.is_configured() {
+            let seed = self.canonicalizer.normalize(&site.url);
+            url_queue.push_back(CrawlUrl::new(seed, 0));
+        }
+
+        loop {
+            // shutting down, put the urls of the local queue back for the next instance
+            if *self.stop.borrow() {
+                let urls = url_queue.drain(..).collect::<Vec<_>>();
                 info!(
-                    "crawl_loop: crawling url {} on depth {}",
-                    url_str, crl.depth
+                    "host_loop: stopping worker for {host}, returning {} urls",
+                    urls.len()
                 );
+                if let Err(e) = self
+                    .reschedule(&host, &site.site, &urls, Duration::ZERO, None)
+                    .await
+                {
+                    error!("host_loop: error while returning urls of {host} {e}");
+                }
+                return;
+            }
 
-                if let Err(e) = self.crawl(crl.url, crl.depth).await {
+            if url_queue.is_empty() && site.budget.is_limited() {
+                // other hosts and instances crawl for the same site
+                if let Err(e) = site.budget.load(&self.db, &site.site).await {
                     error!(
-                        "crawl_loop: error while crawling url: {} on depth: {}, e: {}",
-                        url_str, crl.depth, e
+                        "host_loop: error while loading crawl budget of {} {e}",
+                        site.site
                     );
                 }
+                if let Some(left) = site.budget.exhausted() {
+                    info!(
+                        "host_loop: crawl budget of {} exhausted, {host} sleeps for {}s",
+                        site.site,
+                        left.as_secs()
+                    );
+                    tokio::time::sleep(left).await;
+                    continue;
+                }
+            }
+
+            if url_queue.is_empty() {
+                info!("host_loop: {host} url queue is empty, trying to populate");
+                // only lease what the host timer lets us crawl before the lease runs out
+                let limit = (Self::LEASE_DURATION.as_secs_f64()
+                    / site.timer.interval().as_secs_f64().max(1.0))
+                    as usize;
+                let limit = limit.clamp(1, Self::MAX_QUEUE_SIZE);
+
+                match self.populate_urls(&host, limit).await {
+                    Ok(urls) => url_queue = urls,
+                    Err(e) => error!("host_loop: error while populating urls for {host} {e}"),
+                }
+            }
 
-                index += 1;
+            if url_queue.is_empty() {
+                tokio::time::sleep(Duration::new(3, 0)).await;
+                continue;
             }
 
-            self.url_queue.clear();
-            tokio::time::sleep(Duration::new(3, 0)).await;
+            // the site used up its budget, hand the urls back until the next cycle
+            if let Some(left) = site.budget.exhausted() {
+                let urls = url_queue.drain(..).collect::<Vec<_>>();
+                info!(
+                    "host_loop: crawl budget of {} exhausted for {}s, rescheduling {} urls of {host}",
+                    site.site,
+                    left.as_secs(),
+                    urls.len()
+                );
+                if let Err(e) = self
+                    .reschedule(
+                        &host,
+                        &site.site,
+                        &urls,
+                        left,
+                        Some("crawl budget exhausted"),
+                    )
+                    .await
+                {
+                    error!("host_loop: error while rescheduling urls for {host} {e}");
+                }
+                continue;
+            }
+
+            // host is rate limited for a while, don't hold its urls in memory
+            let wait = site.timer.remaining();
+            if wait > Self::MAX_TIMER_WAIT {
+                let urls = url_queue.drain(..).collect::<Vec<_>>();
+                info!(
+                    "host_loop: {host} rate limited for {}s, rescheduling {} urls",
+                    wait.as_secs(),
+                    urls.len()
+                );
+                if let Err(e) = self
+                    .reschedule(&host, &site.site, &urls, wait, Some("rate limit exceeded"))
+                    .await
+                {
+                    error!("host_loop: error while rescheduling urls for {host} {e}");
+                }
+                continue;
+            }
+
+            let Some(mut crl) = url_queue.pop_front() else {
+                continue;
+            };
+            let url_str = crl.url.to_string();
+
+            info!("host_loop: crawling url {} on depth {}", url_str, crl.depth);
+
+            let res = self
+                .crawl(&mut site, &crl)
+                .await
+                .unwrap_or_else(|e| Crawled::Retry {
+                    after: None,
+                    reason: e.to_string(),
+                });
+
+            match res {
+                Crawled::Done => {}
+                // crawled successfully, keep the url around for its next re-crawl
+                Crawled::Recrawl(interval) => {
+                    crl.attempts = 0;
+                    if let Err(e) = self
+                        .reschedule(&host, &site.site, &[crl], interval, None)
+                        .await
+                    {
+                        error!("host_loop: error while scheduling re-crawl of url: {url_str} {e}");
+                    }
+                    continue;
+                }
+                Crawled::Retry { after, reason } => {
+                    error!(
+                        "host_loop: error while crawling url: {} on depth: {}, e: {}",
+                        url_str, crl.depth, reason
+                    );
+
+                    crl.attempts += 1;
+                    if crl.attempts < Self::MAX_ATTEMPTS {
+                        // the server knows best when it can take the url again
+                        let delay = after
+                            .unwrap_or_else(|| Self::backoff(crl.attempts))
+                            .min(Self::MAX_BACKOFF);
+                        if let Err(e) = self
+                            .reschedule(&host, &site.site, &[crl], delay, Some(&reason))
+                            .await
+                        {
+                            error!("host_loop: error while rescheduling url: {url_str} {e}");
+                        }
+                        continue;
+                    }
+
+                    warn!(
+                        "host_loop: giving up on {url_str} after {} attempts",
+                        crl.attempts
+                    );
+                    if let Err(e) = self.dead_letter(&host, &crl, None, &reason).await {
+                        error!("host_loop: error while recording failure of url: {url_str} {e}");
+                    }
+                }
+                Crawled::Failed { status, reason } => {
+                    warn!("host_loop: {url_str} failed permanently, {reason}");
+                    if let Err(e) = self.dead_letter(&host, &crl, status, &reason).await {
+                        error!("host_loop: error while recording failure of url: {url_str} {e}");
+                    }
+                }
+            }
+
+            if let Err(e) = self.complete(&crl.url).await {
+                error!("host_loop: error while removing url: {url_str} from queue {e}");
+            }
         }
     }
 
-    pub async fn check_valid(&mut self, url: &Url, depth: u32) -> Result<(bool, &str)> {
+    // the url's outcome has been recorded, drop it from crawler_queue
+    async fn complete(&self, url: &Url) -> Result<()> {
+        let mut pool = self.db.get_pg().await?;
+
+        sqlx::query("DELETE FROM crawler_queue WHERE url = $1 AND leased_by = $2")
+            .bind(url.as_str())
+            .bind(&self.worker_id)
+            .execute(pool.acquire().await?)
+            .await?;
+
+        Ok(())
+    }
+
+    // exponential backoff after `attempts` failed fetches
+    fn backoff(attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        Self::BASE_BACKOFF
+            .saturating_mul(factor)
+            .min(Self::MAX_BACKOFF)
+    }
+
+    // puts urls back into crawler_queue and releases their lease, to be claimed again once `delay` has passed
+    async fn reschedule(
+        &self,
+        host: &str,
+        site: &str,
+        urls: &[CrawlUrl],
+        delay: Duration,
+        reason: Option<&str>,
+    ) -> Result<()> {
+        let (urls, (depths, (attempts, hops))): (Vec<_>, (Vec<_>, (Vec<_>, Vec<_>))) = urls
+            .iter()
+            .map(|u| {
+                let counts = (u.attempts as i32, u.hops as i32);
+                (u.url.to_string(), (u.depth as i32, counts))
+            })
+            .unzip();
+        let hosts = vec![host.to_string(); urls.len()];
+        let sites = vec![site.to_string(); urls.len()];
+        let delays = vec![delay.as_secs_f64(); urls.len()];
+        let reasons = vec![reason.map(|r| r.to_string()); urls.len()];
+
+        let mut pool = self.db.get_pg().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO crawler_queue (url, host, depth, next_fetch_at, attempts, last_error, site, hops)
+                SELECT url, host, depth, now() + make_interval(secs => delay), attempts, last_error, site, hops
+                FROM UNNEST($1::text[], $2::text[], $3::int[], $4::float8[], $5::int[], $6::text[],
+                    $7::text[], $8::int[])
+                    AS t(url, host, depth, delay, attempts, last_error, site, hops)
+                ON CONFLICT (url) DO UPDATE SET
+                    next_fetch_at = EXCLUDED.next_fetch_at,
+                    attempts = EXCLUDED.attempts,
+                    last_error = EXCLUDED.last_error,
+                    leased_by = NULL,
+                    lease_expires_at = NULL
+            "#,
+        )
+        .bind(&urls)
+        .bind(&hosts)
+        .bind(&depths)
+        .bind(&delays)
+        .bind(&attempts)
+        .bind(&reasons)
+        .bind(&sites)
+        .bind(&hops)
+        .execute(pool.acquire().await?)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn check_valid(&self, url: &Url, depth: u32) -> Result<(bool, &str)> {
         let host = url.host();
-        let key = url.to_string();
+        let key = self.canonicalizer.canonicalize(url).to_string();
 
         if host.is_none() {
             return Ok((false, "No host found"));
         }
         let host = host.clone().unwrap().to_string();
 
-        // check if url host is in self.site_map
-        let site = self.site_map.get(&host);
-        if site.is_none() {
-            return Ok((false, "host not found in configured sites"));
-        }
-        let mut site = site.unwrap().to_owned();
+        let invalid = {
+            let site_map = self.site_map.read().unwrap();
 
-        // check if url depth has reached
-        if let Some(site_depth) = site.depth {
-            if depth >= site_depth {
-                return Ok((false, "site depth reached"));
+            // check if url host is in self.site_map
+            match site_map.get(&host) {
+                None => Some("host not found in configured sites"),
+                // check if url depth has reached
+                Some(site) if site.depth.is_some_and(|d| depth >= d) => Some("site depth reached"),
+                // check if url is within the site's path prefix and include / exclude patterns
+                Some(site) if !site.scope.allows(url) => Some("outside of site scope"),
+                // check if urls is allowed according to robots.txt
+                Some(site) if !site.is_allowed(url) => Some("not allowed by robots.txt"),
+                Some(_) => None,
             }
+        };
+        if let Some(reason) = invalid {
+            return Ok((false, reason));
         }
 
-        // check if urls is allowed according to robots.txt
-        if !site.is_allowed(url.path()) {
-            return Ok((false, "not allowed by robots.txt"));
+        // sitemaps and urls queued before the parser checked for traps
+        if let Some(trap) = detect_trap(url) {
+            self.db
+                .count_traps(&[(host, trap.as_str().to_string(), 1)])
+                .await?;
+            return Ok((false, trap.as_str()));
         }
 
         // check if url is in redis if yes skip
@@ -199,59 +925,540 @@ impl Crawler {
             return Ok((false, "url exists in redis"));
         }
 
-        // check if timer rate limit has passed
-        if !site.timer.can_send() {
-            // add url back to queue
-            self.url_queue.push(CrawlUrl::new(url.clone(), depth));
-            warn!("site timer for: {:?}", site.timer);
-            return Ok((false, "rate limit exceeded"));
+        // aliases are crawled under the url they redirect to
+        let mut pool = self.db.get_pg().await?;
+        let alias = sqlx::query_as::<_, (String,)>("SELECT url FROM url_alias WHERE alias = $1")
+            .bind(&key)
+            .fetch_optional(pool.acquire().await?)
+            .await?;
+        if alias.is_some() {
+            return Ok((false, "url is an alias of another url"));
         }
 
         Ok((true, "all checks passed"))
     }
 
-    pub async fn crawl(&mut self, url: Url, depth: u32) -> Result<()> {
+    pub async fn crawl(&self, site: &mut Sites, crl: &CrawlUrl) -> Result<Crawled> {
+        let (url, depth) = (crl.url.clone(), crl.depth);
         let (valid, reason) = self.check_valid(&url, depth).await?;
         if !valid {
             warn!("crawl: invalid url {url} at depth {depth}, reason: {reason}");
-            return Ok(());
+            return Ok(Crawled::Done);
         }
 
-        let mins_10 = 60 * 10;
-        let days_7 = 60 * 60 * 24 * 7;
+        // follow redirects on this host by hand, the urls that redirected become aliases of the final one
+        let mut url = url;
+        let mut chain = vec![];
 
-        // send request
-        let res = self
-            .client
-            .get(url.clone())
-            .header(USER_AGENT, FOXEYE_USER_AGENT)
-            .send()
+        let (key, state, fetched) = loop {
+            // politeness for this host first, then the global in-flight limit
+            site.timer.wait().await;
+            let _permit = self.permits.acquire().await?;
+
+            // redis and crawl_state are keyed on the canonical url, the request goes to the url as queued
+            let key = self.canonicalizer.canonicalize(&url).to_string();
+            let state = CrawlState::load(&self.db, &key).await?;
+
+            let started = Instant::now();
+            let (fetched, received) = self.fetch(&url, state.as_ref(), site.max_body_size).await?;
+            self.charge(site, &fetched, received, started.elapsed())
+                .await;
+
+            let target = match fetched {
+                FetchOutcome::Redirect(target) => target,
+                fetched => break (key, state, fetched),
+            };
+            info!("crawl: {url} redirects to {target}");
+
+            if target == url || chain.contains(&target) {
+                return Err(anyhow!("redirect loop at {target}"));
+            }
+            if chain.len() >= MAX_REDIRECTS {
+                return Err(anyhow!(
+                    "more than {MAX_REDIRECTS} redirects, last to {target}"
+                ));
+            }
+            chain.push(url);
+
+            // other hosts have their own timer and robots.txt, hand the target over through the queue
+            // so do targets check_valid would reject, it records why when the target comes up
+            if target.host_str() != chain[0].host_str()
+                || !site.scope.allows(&target)
+                || !site.is_allowed(&target)
+                || detect_trap(&target).is_some()
+            {
+                self.hand_over(site, &chain, target, crl).await?;
+                return Ok(Crawled::Done);
+            }
+            url = target;
+        };
+
+        if let Some(after) = fetched.retry_after() {
+            info!("crawl: {url} asks to retry after {}s", after.as_secs());
+            site.timer.delay(after);
+        }
+
+        if fetched.is_transient() {
+            return Ok(Crawled::Retry {
+                after: fetched.retry_after(),
+                reason: fetched.reason(),
+            });
+        }
+
+        if fetched.is_gone() {
+            self.tombstone(&key).await?;
+        }
+
+        // every other client error and redirects we can't follow are permanent
+        if let Some(status) = fetched.status() {
+            return Ok(Crawled::Failed {
+                status: Some(status),
+                reason: fetched.reason(),
+            });
+        }
+
+        self.save_aliases(&chain, &key).await?;
+        let interval = self
+            .save_page(url.clone(), &key, &site.site, crl, state, fetched)
             .await?;
 
-        let content_type = res.headers().get(CONTENT_TYPE);
-        
-        if content_type.is_none() {
-            warn!("crawl: no content type found for url {url}");
-            self.db
-                .set_cache(url.as_ref(), vec![], Some(days_7))
+        // the queued url redirected, an alias or just /dir to /dir/
+        // schedule the re-crawl under the real location instead so it doesn't redirect again
+        if chain.is_empty() {
+            return Ok(interval.map_or(Crawled::Done, Crawled::Recrawl));
+        }
+        if let Some(interval) = interval {
+            let host = url.host_str().unwrap_or_default();
+            let crl = CrawlUrl {
+                url: self.canonicalizer.normalize(&url),
+                attempts: 0,
+                ..crl.clone()
+            };
+            self.reschedule(host, &site.site, &[crl], interval, None)
                 .await?;
-            return Ok(());
         }
-        let content_type = content_type.unwrap().to_str()?;
-        let mime_type = content_type.parse::<Mime>()?;
-        
-        if mime_type.type_() != mime::TEXT {
-            warn!("crawl: mime type is note text for url {url}");
-            self.db
-                .set_cache(url.as_ref(), vec![], Some(days_7))
+
+        Ok(Crawled::Done)
+    }
+
+    // worker for a file:// site, indexes the files under its directory and keeps looking for changes
+    async fn local_loop(&self, key: String, site: Sites) {
+        info!("local_loop: starting worker for {key}");
+        let mut stop = self.stop.subscribe();
+
+        loop {
+            match self.scan_local(&site).await {
+                Ok((modified, gone)) => {
+                    info!("local_loop: scanned {key}, {modified} files modified, {gone} gone")
+                }
+                Err(e) => error!("local_loop: error while scanning {key} {e}"),
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(Self::LOCAL_SCAN_INTERVAL) => {}
+                _ = stop.wait_for(|s| *s) => {
+                    info!("local_loop: stopping worker for {key}");
+                    return;
+                }
+            }
+        }
+    }
+
+    // returns how many files were modified since the last scan and how many are gone
+    async fn scan_local(&self, site: &Sites) -> Result<(usize, usize)> {
+        let root = site
+            .url
+            .to_file_path()
+            .map_err(|_| anyhow!("{} is not a local path", site.url))?;
+        let prefix = Url::from_directory_path(&root)
+            .map_err(|_| anyhow!("{} is not a local directory", root.display()))?;
+
+        let files = spawn_blocking(move || walk(&root)).await??;
+
+        let mut seen = HashSet::new();
+        let mut modified = 0;
+        for file in files {
+            // a partial scan can't tell which files are gone
+            if *self.stop.borrow() {
+                return Ok((modified, 0));
+            }
+            if !site.scope.allows(&file.url) {
+                continue;
+            }
+
+            let key = self.canonicalizer.canonicalize(&file.url).to_string();
+            seen.insert(key.clone());
+            let path = file.path.clone();
+            match self.index_file(site, &key, file).await {
+                Ok(true) => modified += 1,
+                Ok(false) => {}
+                Err(e) => error!("scan_local: error while indexing {} {e}", path.display()),
+            }
+        }
+
+        let gone = self.forget_files(prefix.as_str(), &seen).await?;
+        Ok((modified, gone))
+    }
+
+    // the file's mtime stands in for last-modified, save_page compares the content hash of modified files
+    async fn index_file(&self, site: &Sites, key: &str, file: LocalFile) -> Result<bool> {
+        if file.size > site.max_body_size as u64 {
+            warn!(
+                "scan_local: {} is larger than {} bytes",
+                file.path.display(),
+                site.max_body_size
+            );
+            return Ok(false);
+        }
+
+        let modified = OffsetDateTime::from(file.modified).format(&Rfc3339)?;
+        let state = CrawlState::load(&self.db, key).await?;
+        if state.as_ref().and_then(|s| s.last_modified.as_deref()) == Some(modified.as_str()) {
+            return Ok(false);
+        }
+
+        let bytes = tokio::fs::read(&file.path).await?;
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_str(file.mime.as_ref())?);
+        headers.insert(LAST_MODIFIED, HeaderValue::from_str(&modified)?);
+
+        let fetched = to_page(&file.url, Some(file.mime), &headers, &bytes);
+        let crl = CrawlUrl::new(file.url.clone(), 0);
+        self.save_page(file.url, key, &site.site, &crl, state, fetched)
+            .await?;
+
+        Ok(true)
+    }
+
+    // files under `prefix` indexed before that weren't found again, returns how many
+    async fn forget_files(&self, prefix: &str, seen: &HashSet<String>) -> Result<usize> {
+        let mut pool = self.db.get_pg().await?;
+
+        let known = sqlx::query_as::<_, (String,)>(
+            "SELECT url FROM crawl_state WHERE starts_with(url, $1)",
+        )
+        .bind(prefix)
+        .fetch_all(pool.acquire().await?)
+        .await?;
+
+        let gone = known
+            .into_iter()
+            .map(|(url,)| url)
+            .filter(|url| !seen.contains(url))
+            .collect::<Vec<_>>();
+        for url in &gone {
+            self.tombstone(url).await?;
+            // tombstone keeps the state of urls that never made it into a document
+            sqlx::query("DELETE FROM crawl_state WHERE url = $1")
+                .bind(url)
+                .execute(pool.acquire().await?)
                 .await?;
-            return Ok(());
+            info!("scan_local: {url} is gone");
+        }
+
+        Ok(gone.len())
+    }
+
+    // indexes responses archived in WARC files instead of fetching them, see `crawler import`
+    // records pass the same checks and mime rules as a crawl and reach the parser the same way
+    // with `requeue` the imported pages are re-crawled live from then on, otherwise nothing is ever fetched
+    pub async fn import(&self, paths: &[PathBuf], requeue: bool) -> Result<()> {
+        for path in paths {
+            info!("import: reading {}", path.display());
+            let (tx, mut rx) = mpsc::channel(Self::IMPORT_BUFFER);
+
+            // reading and unzipping blocks, records are handed over as they are read
+            let reader_path = path.clone();
+            let reader = spawn_blocking(move || -> Result<()> {
+                let mut warc = WarcReader::open(&reader_path)?;
+                while let Some(record) = warc.next_record()? {
+                    if tx.blocking_send(record).is_err() {
+                        break;
+                    }
+                }
+                Ok(())
+            });
+
+            let (mut imported, mut skipped) = (0, 0);
+            while let Some(record) = rx.recv().await {
+                match self.import_record(&record, requeue).await {
+                    Ok(true) => imported += 1,
+                    Ok(false) => skipped += 1,
+                    Err(e) => {
+                        error!(
+                            "import: error while importing {} {e}",
+                            record.target_uri().unwrap_or_default()
+                        );
+                        skipped += 1;
+                    }
+                }
+            }
+
+            if let Err(e) = reader.await? {
+                error!("import: error while reading {} {e}", path.display());
+            }
+            info!(
+                "import: {} done, {imported} pages imported, {skipped} records skipped",
+                path.display()
+            );
+        }
+
+        Ok(())
+    }
+
+    // true if the record was indexable and handed to save_page
+    async fn import_record(&self, record: &WarcRecord, requeue: bool) -> Result<bool> {
+        if !record.is_response() {
+            return Ok(false);
+        }
+        let Some(url) = record.target_uri().and_then(|u| Url::parse(u).ok()) else {
+            return Ok(false);
+        };
+
+        let (status, headers, body) = parse_response(&record.block)?;
+        if status != StatusCode::OK {
+            info!("import: skipping {url}, archived with status {status}");
+            return Ok(false);
+        }
+
+        let Some(site) = self.import_site(&url).await? else {
+            warn!("import: invalid url {url}, reason: host not found in configured sites");
+            return Ok(false);
+        };
+        let (crl, _) = self.queued(&url).await?;
+        let (valid, reason) = self.check_valid(&url, crl.depth).await?;
+        if !valid {
+            warn!(
+                "import: invalid url {url} at depth {}, reason: {reason}",
+                crl.depth
+            );
+            return Ok(false);
+        }
+
+        let content_type = content_type(&headers);
+        if let Some(mime) = content_type.as_ref().filter(|m| !is_indexable(m)) {
+            info!("import: mime type {mime} is not indexable for url {url}");
+            return Ok(false);
+        }
+        let bytes = decode_payload(&headers, body)?;
+        if bytes.len() > site.max_body_size {
+            warn!(
+                "import: body of {url} is larger than {} bytes",
+                site.max_body_size
+            );
+            return Ok(false);
+        }
+
+        let fetched = to_page(&url, content_type, &headers, &bytes);
+        // the target is crawled from the archive too if it is in there
+        if let FetchOutcome::Redirect(target) = &fetched {
+            info!("import: {url} refreshes to {target}, skipping");
+            return Ok(false);
+        }
+        let is_page = matches!(fetched, FetchOutcome::Page { .. });
+
+        let key = self.canonicalizer.canonicalize(&url).to_string();
+        let state = CrawlState::load(&self.db, &key).await?;
+        let host = url.host_str().unwrap_or_default().to_string();
+        let interval = self
+            .save_page(url, &key, &site.site, &crl, state, fetched)
+            .await?;
+
+        // asked to, it is re-crawled live from now on like any crawled url, otherwise the queue is left as is
+        if let Some(interval) = interval.filter(|_| requeue) {
+            self.reschedule(&host, &site.site, &[crl], interval, None)
+                .await?;
+        }
+
+        Ok(is_page)
+    }
+
+    // the config of the url's host, read from the sites table the first time the host comes up
+    // hosts covered by a site's domains get their own like in discover_hosts
+    async fn import_site(&self, url: &Url) -> Result<Option<Sites>> {
+        let Some(host) = url.host_str() else {
+            return Ok(None);
+        };
+        if let Some(site) = self.site_map.read().unwrap().get(host) {
+            return Ok(Some(site.clone()));
+        }
+
+        let site = match self.db.get_site(host).await? {
+            Some(row) => Sites::load(&row).await?,
+            None => {
+                let mut parent = None;
+                for row in self.db.get_sites().await? {
+                    let site = Sites::from_row(&row)?;
+                    if site.is_configured() && site.covers(host) {
+                        parent = Some(site);
+                        break;
+                    }
+                }
+                let Some(parent) = parent else {
+                    return Ok(None);
+                };
+                parent.for_host(host).await?
+            }
+        };
+
+        self.site_map
+            .write()
+            .unwrap()
+            .insert(host.to_string(), site.clone());
+        Ok(Some(site))
+    }
+
+    // depth, hops and site of the url if it was queued, urls we never saw start at the site root
+    async fn queued(&self, url: &Url) -> Result<(CrawlUrl, Option<String>)> {
+        // queued in the form it is fetched in
+        let url = self.canonicalizer.normalize(url);
+        let mut pool = self.db.get_pg().await?;
+
+        let queued = sqlx::query_as::<_, (i32, i32, Option<String>)>(
+            "SELECT depth, hops, COALESCE(site, host) FROM crawler_queue WHERE url = $1",
+        )
+        .bind(url.as_str())
+        .fetch_optional(pool.acquire().await?)
+        .await?;
+
+        let mut crl = CrawlUrl::new(url, 0);
+        let Some((depth, hops, site)) = queued else {
+            return Ok((crl, None));
+        };
+        crl.depth = depth.max(0) as u32;
+        crl.hops = hops.max(0) as u32;
+        Ok((crl, site))
+    }
+
+    // sends a single request, conditional if we crawled this url before
+    // returns the outcome and the body bytes received for it
+    async fn fetch(
+        &self,
+        url: &Url,
+        state: Option<&CrawlState>,
+        max_size: usize,
+    ) -> Result<(FetchOutcome, usize)> {
+        let mut req = self
+            .client
+            .get(url.clone())
+            .header(USER_AGENT, FOXEYE_USER_AGENT);
+
+        if let Some(state) = state {
+            if let Some(etag) = &state.etag {
+                req = req.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &state.last_modified {
+                req = req.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let req = req.build()?;
+        let request_head = self.warc.as_ref().map(|_| RequestHead::new(&req));
+
+        let res = match self.client.execute(req).await {
+            Ok(res) => res,
+            Err(e) => return Ok((FetchOutcome::from_error(e), 0)),
+        };
+
+        // archived with whatever part of the body was downloaded
+        let response_head = request_head.as_ref().map(|_| ResponseHead::new(&res));
+        let (outcome, body, received) = read_response(url, res, max_size).await?;
+        if let (Some(request), Some(response)) = (request_head, response_head) {
+            self.archive(request, response, body).await;
+        }
+
+        Ok((outcome, received))
+    }
+
+    async fn archive(&self, request: RequestHead, response: ResponseHead, body: Option<Vec<u8>>) {
+        if let Some(warc) = &self.warc {
+            warc.write(request, response, body).await;
+        }
+    }
+
+    // records the response to the final request, returns when the page should be crawled again
+    async fn save_page(
+        &self,
+        url: Url,
+        key: &str,
+        site: &str,
+        crl: &CrawlUrl,
+        state: Option<CrawlState>,
+        fetched: FetchOutcome,
+    ) -> Result<Option<Duration>> {
+        let mins_10 = 60 * 10;
+        let days_7 = 60 * 60 * 24 * 7;
+
+        let mut state = state.unwrap_or_else(|| CrawlState::new(crl.refresh_hint));
+
+        let (res, content_type, etag, last_modified, headers) = match fetched {
+            FetchOutcome::Page {
+                body,
+                content_type,
+                etag,
+                last_modified,
+                headers,
+            } => (body, content_type, etag, last_modified, headers),
+            FetchOutcome::NotModified => {
+                state.unchanged();
+                state.follow_hint(crl.refresh_hint);
+                state.save(&self.db, key, false).await?;
+                self.db
+                    .set_cache(key, vec![], Some(state.interval_secs()))
+                    .await?;
+
+                info!(
+                    "crawl: {url} not modified, next crawl in {}s",
+                    state.interval_secs()
+                );
+                return Ok(Some(state.interval()));
+            }
+            // nothing we can index, failures are handled by crawl
+            _ => {
+                self.db.set_cache(key, vec![], Some(days_7)).await?;
+                return Ok(None);
+            }
+        };
+
+        state.etag = etag;
+        state.last_modified = last_modified;
+
+        // servers without validators still answer 200, compare content to find out if it changed
+        let hash = format!("{:x}", Sha256::digest(res.as_bytes()));
+        let changed = state.content_hash.as_deref() != Some(hash.as_str());
+        if changed {
+            state.changed(hash);
+        } else {
+            state.unchanged();
+        }
+        state.follow_hint(crl.refresh_hint);
+        state.save(&self.db, key, changed).await?;
+
+        // save url into cache
+        self.db
+            .set_cache(key, vec![], Some(state.interval_secs()))
+            .await?;
+
+        if !changed {
+            info!(
+                "crawl: {url} unchanged, next crawl in {}s",
+                state.interval_secs()
+            );
+            return Ok(Some(state.interval()));
         }
 
-        let res = res.text().await?;
         let id = Ulid::new().to_string();
 
-        let message = CrawlMessage::new(id.clone(), res, depth, url.clone().to_string());
+        let message = CrawlMessage::new(
+            id.clone(),
+            res,
+            crl.depth,
+            url.to_string(),
+            content_type,
+            site.to_string(),
+            crl.hops,
+        )
+        .with_headers(headers);
         let message = serde_json::to_string(&message)?;
 
         // save document into cache
@@ -259,16 +1466,307 @@ impl Crawler {
             .set_cache(&id, message.into_bytes(), Some(mins_10))
             .await?;
 
-        // save url into cache
-        self.db
-            .set_cache(url.as_ref(), vec![], Some(days_7))
-            .await?;
-
         info!("saved crawled content in redis with id: {id}");
         // tokio::time::sleep(Duration::from_millis(200)).await; // let redis save id
         self.amq.publish(id).await?;
         info!("sent id in amq");
 
+        Ok(Some(state.interval()))
+    }
+
+    // a redirect we don't follow now, the target is queued for the worker of its host
+    async fn hand_over(
+        &self,
+        site: &Sites,
+        chain: &[Url],
+        target: Url,
+        crl: &CrawlUrl,
+    ) -> Result<()> {
+        let target = self.canonicalizer.normalize(&target);
+        let host = target.host_str().unwrap_or_default().to_string();
+
+        // the target counts as a link of the page that redirected
+        let Some((target_site, hops)) = self.site_for(site, &host, crl.hops) else {
+            warn!(
+                "crawl: {} redirects to {target}, host not found in configured sites",
+                chain[0]
+            );
+            for url in chain {
+                let key = self.canonicalizer.canonicalize(url).to_string();
+                self.db
+                    .set_cache(&key, vec![], Some(60 * 60 * 24 * 7))
+                    .await?;
+            }
+            return Ok(());
+        };
+
+        let key = self.canonicalizer.canonicalize(&target).to_string();
+        self.save_aliases(chain, &key).await?;
+        let crl = CrawlUrl {
+            hops,
+            ..CrawlUrl::new(target, crl.depth)
+        };
+        self.reschedule(&host, &target_site, &[crl], Duration::ZERO, None)
+            .await
+    }
+
+    // the configured site a link from `from` to `host` is crawled for, and its hops off the site's hosts
+    fn site_for(&self, from: &Sites, host: &str, hops: u32) -> Option<(String, u32)> {
+        let site_map = self.site_map.read().unwrap();
+
+        let owner = site_map
+            .values()
+            .find(|s| s.is_configured() && s.covers(host));
+        if let Some(site) = owner {
+            return Some((site.site.clone(), 0));
+        }
+
+        (hops < from.external_hops).then(|| (from.site.clone(), hops + 1))
+    }
+
+    // urls that redirected to `url`, search and the crawler only know them by `url` from now on
+    // returns the number of aliases saved, variants of `url` itself are not aliases
+    async fn save_aliases(&self, chain: &[Url], url: &str) -> Result<usize> {
+        let mut aliases = chain
+            .iter()
+            .map(|u| self.canonicalizer.canonicalize(u).to_string())
+            .filter(|a| a != url)
+            .collect::<Vec<_>>();
+        aliases.sort();
+        aliases.dedup();
+
+        if aliases.is_empty() {
+            return Ok(0);
+        }
+
+        let mut pool = self.db.get_pg().await?;
+        let mut tx = pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO url_alias (alias, url)
+                SELECT alias, $2 FROM UNNEST($1::text[]) AS alias
+                ON CONFLICT (alias) DO UPDATE SET url = EXCLUDED.url
+            "#,
+        )
+        .bind(&aliases)
+        .bind(url)
+        .execute(&mut *tx)
+        .await?;
+
+        // the target may have redirected itself in the past
+        sqlx::query("DELETE FROM url_alias WHERE alias = $1")
+            .bind(url)
+            .execute(&mut *tx)
+            .await?;
+
+        // documents saved under an alias before it started redirecting
+        sqlx::query("DELETE FROM document WHERE url = ANY($1)")
+            .bind(&aliases)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        info!("crawl: recorded {} aliases of {url}", aliases.len());
+        Ok(aliases.len())
+    }
+
+    // the page is gone, keep its document as a tombstone so search stops returning it
+    async fn tombstone(&self, url: &str) -> Result<()> {
+        let mut pool = self.db.get_pg().await?;
+        let mut tx = pool.begin().await?;
+
+        let doc = sqlx::query_as::<_, (String,)>(
+            "UPDATE document SET gone_at = now() WHERE url = $1 AND gone_at IS NULL RETURNING doc_id",
+        )
+        .bind(url)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some((doc_id,)) = doc {
+            sqlx::query("DELETE FROM chunk WHERE doc_id = $1")
+                .bind(&doc_id)
+                .execute(&mut *tx)
+                .await?;
+            // if the page comes back it is a new page, not an unchanged one
+            sqlx::query("DELETE FROM crawl_state WHERE url = $1")
+                .bind(url)
+                .execute(&mut *tx)
+                .await?;
+            info!("crawl: tombstoned document {doc_id} of {url}");
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // every request counts towards the site's budget, tracked even when it has no limits
+    // `received` is the size of the body as downloaded, before decoding, whatever the outcome
+    async fn charge(
+        &self,
+        site: &mut Sites,
+        fetched: &FetchOutcome,
+        received: usize,
+        elapsed: Duration,
+    ) {
+        // redirects, 304s, skipped bodies and errors cost a request but aren't pages
+        let pages = match fetched {
+            FetchOutcome::Page { .. } => 1,
+            _ => 0,
+        };
+        if let Err(e) = site
+            .budget
+            .charge(&self.db, &site.site, pages, received, elapsed)
+            .await
+        {
+            error!(
+                "charge: error while updating crawl budget of {} {e}",
+                site.site
+            );
+        }
+    }
+
+    // the url failed for good, record why before it leaves crawler_queue
+    async fn dead_letter(
+        &self,
+        host: &str,
+        crl: &CrawlUrl,
+        status: Option<StatusCode>,
+        reason: &str,
+    ) -> Result<()> {
+        let mut pool = self.db.get_pg().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO crawl_errors (url, host, depth, status, error, attempts)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT (url) DO UPDATE SET
+                    status = EXCLUDED.status,
+                    error = EXCLUDED.error,
+                    attempts = EXCLUDED.attempts
+            "#,
+        )
+        .bind(crl.url.as_str())
+        .bind(host)
+        .bind(crl.depth as i32)
+        .bind(status.map(|s| s.as_u16() as i32))
+        .bind(reason)
+        .bind(crl.attempts as i32)
+        .execute(pool.acquire().await?)
+        .await?;
+
+        // don't fetch it again when it is rediscovered this week
+        let key = self.canonicalizer.canonicalize(&crl.url).to_string();
+        self.db
+            .set_cache(&key, vec![], Some(60 * 60 * 24 * 7))
+            .await?;
+
         Ok(())
     }
 }
+
+// the outcome of a response, its body if it was read in full and the body bytes received
+// redirects, errors and bodies declared as not indexable are not downloaded
+pub async fn read_response(
+    url: &Url,
+    res: Response,
+    max_size: usize,
+) -> Result<(FetchOutcome, Option<Vec<u8>>, usize)> {
+    if res.status() == StatusCode::NOT_MODIFIED {
+        return Ok((FetchOutcome::NotModified, None, 0));
+    }
+
+    if res.status().is_redirection() {
+        let outcome = FetchOutcome::from_redirect(url, res.status(), res.headers());
+        return Ok((outcome, None, 0));
+    }
+
+    if let Some(outcome) = FetchOutcome::from_status(res.status(), res.headers()) {
+        return Ok((outcome, None, 0));
+    }
+
+    // content-type may be missing or useless, in which case the body is sniffed below
+    let content_type = content_type(res.headers());
+
+    // don't download what we won't index
+    if let Some(mime) = content_type.as_ref().filter(|m| !is_indexable(m)) {
+        warn!("crawl: mime type {mime} is not indexable for url {url}");
+        return Ok((FetchOutcome::NotIndexable, None, 0));
+    }
+
+    let headers = res.headers().clone();
+    let (bytes, received) = read_counted(res, max_size).await;
+    let bytes = match bytes {
+        Ok(Some(bytes)) => bytes,
+        Ok(None) => {
+            warn!("crawl: body of {url} is larger than {max_size} bytes");
+            return Ok((FetchOutcome::TooLarge, None, received));
+        }
+        Err(e) => return Ok((FetchOutcome::from_error(e), None, received)),
+    };
+
+    let page = to_page(url, content_type, &headers, &bytes);
+    Ok((page, Some(bytes), received))
+}
+
+// what an indexable body becomes, for live fetches and archived responses alike
+fn to_page(
+    url: &Url,
+    content_type: Option<Mime>,
+    headers: &HeaderMap,
+    bytes: &[u8],
+) -> FetchOutcome {
+    let mime = match content_type {
+        Some(mime) => mime,
+        None => {
+            let mime = sniff(bytes);
+            info!("crawl: no usable content type for url {url}, sniffed {mime}");
+            if !is_indexable(&mime) {
+                return FetchOutcome::NotIndexable;
+            }
+            mime
+        }
+    };
+
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+    let headers = headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect::<Vec<_>>();
+
+    // binary formats travel to the parser base64 encoded
+    if is_binary(&mime) {
+        return FetchOutcome::Page {
+            body: BASE64_STANDARD.encode(bytes),
+            content_type: mime.essence_str().to_string(),
+            etag,
+            last_modified,
+            headers,
+        };
+    }
+
+    let charset = mime.get_param(mime::CHARSET).map(|c| c.as_str());
+    let body = decode(bytes, charset);
+
+    if let Some(target) = meta_refresh(&body).and_then(|t| url.join(&t).ok()) {
+        if target != *url {
+            return FetchOutcome::Redirect(target);
+        }
+    }
+
+    FetchOutcome::Page {
+        body,
+        content_type: mime.essence_str().to_string(),
+        etag,
+        last_modified,
+        headers,
+    }
+}
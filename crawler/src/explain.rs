@@ -0,0 +1,437 @@
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use reqwest::header::USER_AGENT;
+use reqwest::Client;
+use sqlx::Acquire;
+use url::Url;
+
+use db::Db;
+use parser::extract::extract;
+use parser::formats::DocumentFormat;
+use parser::robots::RobotsDirectives;
+use utils::{detect_trap, Canonicalizer};
+
+use crate::body::{content_type, detect_encoding, is_binary};
+use crate::config::{Sites, FOXEYE_USER_AGENT};
+use crate::crawler::{read_response, Crawler};
+use crate::outcome::FetchOutcome;
+
+// output of `crawler explain`, a line per check or finding
+// the first check that fails is why the url isn't crawled or indexed
+#[derive(Debug, Default)]
+pub struct Report {
+    failed: Option<String>,
+}
+
+impl Report {
+    pub fn new(url: &Url) -> Self {
+        println!("explaining {url}");
+        Report::default()
+    }
+
+    pub fn info(&self, name: &str, detail: impl Display) {
+        println!("       {name:<10} {detail}");
+    }
+
+    pub fn check(&mut self, name: &str, passed: bool, detail: impl Display) {
+        let mark = if passed { "[ok]  " } else { "[fail]" };
+        println!("{mark} {name:<10} {detail}");
+
+        if !passed && self.failed.is_none() {
+            self.failed = Some(format!("{name}, {detail}"));
+        }
+    }
+
+    pub fn finish(self) {
+        match self.failed {
+            None => println!("verdict: crawled and indexed"),
+            Some(reason) => println!("verdict: not indexed, {reason}"),
+        }
+    }
+}
+
+// what `crawler explain <url>` needs, read-only
+// no sites are imported, no queues declared and no WARC written, only the site of the url is loaded
+pub struct Explainer {
+    db: Db,
+    client: Client,
+    canonicalizer: Canonicalizer,
+}
+
+// the url's row in crawler_queue, looked up by its key
+struct Queued {
+    depth: u32,
+    hops: u32,
+    site: String,
+    wait: Duration, // until next_fetch_at
+    last_error: Option<String>,
+}
+
+impl Explainer {
+    pub async fn new() -> Result<Self> {
+        Ok(Explainer {
+            db: Db::new(1).await?,
+            client: Crawler::client()?,
+            canonicalizer: Canonicalizer::load_config()?,
+        })
+    }
+
+    // runs the checks of check_valid and crawl, fetches the url and extracts it like the parser would
+    // and prints why it is or isn't indexed, nothing is written or counted
+    pub async fn explain(&self, url: &Url) -> Result<()> {
+        let mut report = Report::new(url);
+        let canonical = self.canonicalizer.canonicalize(url);
+        let key = canonical.to_string();
+        report.info("canonical", &key);
+
+        let queued = self.queued(&canonical).await?;
+        match &queued {
+            Some(q) => report.info(
+                "queue",
+                format!(
+                    "queued for {} at depth {} with {} external hops",
+                    q.site, q.depth, q.hops
+                ),
+            ),
+            None => report.info("queue", "not queued, checked at depth 0"),
+        }
+        let depth = queued.as_ref().map_or(0, |q| q.depth);
+
+        let host = url.host_str().unwrap_or_default().to_string();
+        let queued_for = queued.as_ref().map(|q| q.site.as_str());
+        let Some(site) = self.site(&host, queued_for).await? else {
+            report.check(
+                "host",
+                false,
+                format!("{host} is not a configured site nor covered by one"),
+            );
+            report.finish();
+            return Ok(());
+        };
+        let detail = if site.is_configured() {
+            format!("{host} is a configured site")
+        } else {
+            format!("{host} is crawled for {}", site.site)
+        };
+        report.check("host", true, detail);
+        report.check(
+            "paused",
+            !site.paused,
+            if site.paused {
+                "site is paused"
+            } else {
+                "site is active"
+            },
+        );
+
+        match site.depth {
+            Some(max) => report.check(
+                "depth",
+                depth < max,
+                format!("depth {depth}, the site is crawled below depth {max}"),
+            ),
+            None => report.check("depth", true, "site has no depth limit"),
+        }
+
+        let prefix = site.scope.path_prefix();
+        if site.scope.allows(url) {
+            report.check(
+                "scope",
+                true,
+                format!("within {prefix} and the site's patterns"),
+            );
+        } else {
+            report.check(
+                "scope",
+                false,
+                format!("outside of {prefix} or excluded by the site's patterns"),
+            );
+        }
+
+        let robots = match site.robots_rule(url) {
+            _ if site.robots.disallows_all() => {
+                "robots.txt is unreachable, everything is disallowed".to_string()
+            }
+            Some(rule) => format!("\"{rule}\" matches"),
+            None => "no rule matches".to_string(),
+        };
+        let allowed = site.is_allowed(url);
+        report.check("robots", allowed, robots);
+
+        match detect_trap(url) {
+            Some(trap) => report.check(
+                "trap",
+                false,
+                format!("looks like a crawl trap, {}", trap.as_str()),
+            ),
+            None => report.check("trap", true, "no crawl trap pattern"),
+        }
+
+        if self.db.exists(&key).await? {
+            report.check(
+                "seen",
+                false,
+                "key exists in redis, skipped until it expires",
+            );
+        } else {
+            report.check("seen", true, "key not in redis");
+        }
+
+        let mut pool = self.db.get_pg().await?;
+        let alias = sqlx::query_as::<_, (String,)>("SELECT url FROM url_alias WHERE alias = $1")
+            .bind(&key)
+            .fetch_optional(pool.acquire().await?)
+            .await?;
+        match alias {
+            Some((target,)) => report.check(
+                "alias",
+                false,
+                format!("alias of {target}, crawled as that url"),
+            ),
+            None => report.check("alias", true, "not an alias of another url"),
+        }
+
+        if site.budget.is_limited() {
+            let mut budget = site.budget.clone();
+            budget.load(&self.db, &site.site).await?;
+            match budget.exhausted() {
+                Some(left) => report.check(
+                    "budget",
+                    false,
+                    format!(
+                        "crawl budget of {} exhausted for {}s",
+                        site.site,
+                        left.as_secs()
+                    ),
+                ),
+                None => report.check("budget", true, "crawl budget left"),
+            }
+        } else {
+            report.check("budget", true, "site has no crawl budget");
+        }
+
+        // a queued url isn't claimed before its next_fetch_at, pushed back by retry backoff, re-crawls,
+        // the host's timer and the budget, the host's own request interval only spaces out due urls
+        match &queued {
+            Some(q) if !q.wait.is_zero() => {
+                let reason = match &q.last_error {
+                    Some(e) => format!("backing off after {e}"),
+                    None => "waiting for its re-crawl".to_string(),
+                };
+                report.check(
+                    "schedule",
+                    false,
+                    format!(
+                        "next_fetch_at in crawler_queue is {}s away, {reason}",
+                        q.wait.as_secs()
+                    ),
+                );
+            }
+            Some(_) => report.check("schedule", true, "next_fetch_at in crawler_queue is due"),
+            None => report.check("schedule", true, "not queued, nothing holds it back"),
+        }
+        report.info(
+            "interval",
+            format!(
+                "one request every {:.1}s to {host}, from rps and crawl-delay",
+                site.timer.interval().as_secs_f64()
+            ),
+        );
+
+        if !allowed {
+            report.info("fetch", "not fetched, robots.txt disallows it");
+            report.finish();
+            return Ok(());
+        }
+
+        // unconditional so there is a body to look at, not archived and not charged to the budget
+        let started = Instant::now();
+        let res = self
+            .client
+            .get(url.clone())
+            .header(USER_AGENT, FOXEYE_USER_AGENT)
+            .send()
+            .await;
+        let res = match res {
+            Ok(res) => res,
+            Err(e) => {
+                report.check("fetch", false, FetchOutcome::from_error(e).reason());
+                report.finish();
+                return Ok(());
+            }
+        };
+        let status = res.status();
+        let header_type = content_type(res.headers());
+        let (fetched, bytes, received) = read_response(url, res, site.max_body_size).await?;
+        report.info(
+            "fetch",
+            format!(
+                "{status} in {}ms, {received} bytes",
+                started.elapsed().as_millis()
+            ),
+        );
+
+        let (body, content_type, headers) = match fetched {
+            FetchOutcome::Page {
+                body,
+                content_type,
+                headers,
+                ..
+            } => (body, content_type, headers),
+            FetchOutcome::Redirect(target) => {
+                report.check(
+                    "fetch",
+                    false,
+                    format!("redirects to {target}, which is crawled instead"),
+                );
+                report.finish();
+                return Ok(());
+            }
+            FetchOutcome::NotIndexable => {
+                let mime = header_type.map_or("sniffed".to_string(), |m| m.to_string());
+                report.check("mime", false, format!("{mime} is not indexable"));
+                report.finish();
+                return Ok(());
+            }
+            fetched => {
+                report.check("fetch", false, fetched.reason());
+                report.finish();
+                return Ok(());
+            }
+        };
+
+        let source = if header_type.is_some() {
+            "from content-type"
+        } else {
+            "sniffed"
+        };
+        report.check("mime", true, format!("{content_type}, {source}"));
+        let mime = header_type
+            .as_ref()
+            .filter(|m| m.essence_str() == content_type)
+            .cloned()
+            .unwrap_or_else(|| content_type.parse().unwrap_or(mime::TEXT_PLAIN));
+        if is_binary(&mime) {
+            report.info("charset", "binary, sent to the parser base64 encoded");
+        } else if let Some(bytes) = &bytes {
+            let charset = mime.get_param(mime::CHARSET).map(|c| c.as_str());
+            let encoding = detect_encoding(bytes, charset);
+            let source = if charset.is_some() {
+                "from content-type"
+            } else {
+                "from the body"
+            };
+            report.info("charset", format!("{}, {source}", encoding.name()));
+        }
+
+        let Some(format) = DocumentFormat::from_content_type(&content_type, url) else {
+            report.check(
+                "parse",
+                false,
+                format!("the parser doesn't handle {content_type}"),
+            );
+            report.finish();
+            return Ok(());
+        };
+        let extracted = match extract(&body, format, url, &self.canonicalizer) {
+            Ok(extracted) => extracted,
+            Err(e) => {
+                report.check("parse", false, e);
+                report.finish();
+                return Ok(());
+            }
+        };
+
+        report.info("title", &extracted.title);
+        report.info("text", format!("{} chars", extracted.text.chars().count()));
+        if let Some(canonical) = &extracted.canonical {
+            report.info("canonical", format!("{canonical}, declared by the page"));
+        }
+        let robots = RobotsDirectives::from_headers(&headers).merge(extracted.robots);
+        let follow = if robots.nofollow {
+            ", not followed, nofollow"
+        } else {
+            ""
+        };
+        report.info(
+            "links",
+            format!("{} outgoing{follow}", extracted.links.len()),
+        );
+        for link in &extracted.links {
+            report.info("", link);
+        }
+        report.check(
+            "noindex",
+            !robots.noindex,
+            if robots.noindex {
+                "meta robots or X-Robots-Tag say noindex"
+            } else {
+                "indexing is allowed"
+            },
+        );
+
+        report.finish();
+        Ok(())
+    }
+
+    async fn queued(&self, key: &Url) -> Result<Option<Queued>> {
+        // the queue holds urls as they are fetched, the key itself or a directory's key with its slash
+        let mut forms = vec![key.to_string()];
+        if key.path() != "/" {
+            let mut dir = key.clone();
+            dir.set_path(&format!("{}/", key.path()));
+            forms.push(dir.to_string());
+        }
+
+        let mut pool = self.db.get_pg().await?;
+
+        let queued = sqlx::query_as::<_, (i32, i32, String, f64, Option<String>)>(
+            r#"
+            SELECT depth, hops, COALESCE(site, host),
+                GREATEST(EXTRACT(EPOCH FROM next_fetch_at - now()), 0)::float8, last_error
+            FROM crawler_queue
+            WHERE url = ANY($1)
+            ORDER BY next_fetch_at
+            LIMIT 1
+            "#,
+        )
+        .bind(&forms)
+        .fetch_optional(pool.acquire().await?)
+        .await?;
+
+        Ok(queued.map(|(depth, hops, site, wait, last_error)| Queued {
+            depth: depth.max(0) as u32,
+            hops: hops.max(0) as u32,
+            site,
+            wait: Duration::from_secs_f64(wait),
+            last_error,
+        }))
+    }
+
+    // the config the crawler would use for the host, built from its site's row alone
+    // hosts covered through a site's domains or reached through external links are crawled for that site
+    async fn site(&self, host: &str, queued_for: Option<&str>) -> Result<Option<Sites>> {
+        if let Some(row) = self.db.get_site(host).await? {
+            return Ok(Some(Sites::load(&row).await?));
+        }
+
+        let mut parent = None;
+        for row in self.db.get_sites().await? {
+            let site = Sites::from_row(&row)?;
+            if site.covers(host) {
+                parent = Some(site);
+                break;
+            }
+            if queued_for == Some(row.host.as_str()) {
+                parent = Some(site);
+            }
+        }
+
+        match parent {
+            Some(parent) => Ok(Some(parent.for_host(host).await?)),
+            None => Ok(None),
+        }
+    }
+}
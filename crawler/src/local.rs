@@ -0,0 +1,163 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Result;
+use mime::Mime;
+use tracing::warn;
+use url::Url;
+
+// what files of local sites are indexed as, by extension, other files are skipped
+const LOCAL_MIME_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("xhtml", "application/xhtml+xml"),
+    ("md", "text/markdown"),
+    ("markdown", "text/markdown"),
+    ("txt", "text/plain"),
+    ("text", "text/plain"),
+];
+
+// an indexable file found under the directory of a file:// site
+#[derive(Debug)]
+pub struct LocalFile {
+    pub path: PathBuf,
+    pub url: Url,
+    pub mime: Mime,
+    pub modified: SystemTime,
+    pub size: u64,
+}
+
+fn local_mime(path: &Path) -> Option<Mime> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    LOCAL_MIME_TYPES
+        .iter()
+        .find(|(e, _)| *e == ext)
+        .and_then(|(_, mime)| mime.parse().ok())
+}
+
+// every indexable file under `root`, hidden files and directories are skipped
+// symlinks to files are followed, symlinks to directories aren't as they can loop
+pub fn walk(root: &Path) -> Result<Vec<LocalFile>> {
+    let mut files = vec![];
+    let mut dirs = vec![root.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if dir == root => return Err(e.into()),
+            Err(e) => {
+                warn!("walk: can't read directory {} {e}", dir.display());
+                continue;
+            }
+        };
+
+        // an entry that can't be read is skipped like a directory that can't, the rest is still walked
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("walk: can't read an entry of {} {e}", dir.display());
+                    continue;
+                }
+            };
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(e) => {
+                    warn!("walk: can't read {} {e}", path.display());
+                    continue;
+                }
+            };
+            if file_type.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+
+            let Some(mime) = local_mime(&path) else {
+                continue;
+            };
+            let meta = match fs::metadata(&path) {
+                Ok(meta) if meta.is_file() => meta,
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!("walk: can't read {} {e}", path.display());
+                    continue;
+                }
+            };
+            let modified = match meta.modified() {
+                Ok(modified) => modified,
+                Err(e) => {
+                    warn!("walk: can't read the mtime of {} {e}", path.display());
+                    continue;
+                }
+            };
+            let Ok(url) = Url::from_file_path(&path) else {
+                continue;
+            };
+
+            files.push(LocalFile {
+                path,
+                url,
+                mime,
+                modified,
+                size: meta.len(),
+            });
+        }
+    }
+
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use ulid::Ulid;
+
+    use super::*;
+
+    #[test]
+    fn test_walk() {
+        let root = env::temp_dir().join(format!("foxeye-local-{}", Ulid::new()));
+        let files = [
+            "index.html",
+            "guide/setup.MD",
+            "guide/deep/notes.txt",
+            "logo.png",
+            "README",
+            ".git/HEAD.txt",
+            "guide/.draft.md",
+        ];
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "hello").unwrap();
+        }
+
+        let found = walk(&root).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        let found = found
+            .iter()
+            .map(|f| {
+                let path = f.path.strip_prefix(&root).unwrap();
+                (path.to_str().unwrap().to_string(), f.mime.essence_str())
+            })
+            .collect::<Vec<_>>();
+        let expected = [
+            ("guide/deep/notes.txt", "text/plain"),
+            ("guide/setup.MD", "text/markdown"),
+            ("index.html", "text/html"),
+        ];
+        assert_eq!(
+            found,
+            expected.map(|(p, m)| (p.to_string(), m)).to_vec(),
+            "{root:?}"
+        );
+    }
+}
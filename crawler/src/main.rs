@@ -1,15 +1,89 @@
+mod admin;
+mod body;
+mod budget;
 mod config;
 mod crawler;
+mod explain;
+mod local;
+mod outcome;
+mod redirect;
+mod refresh;
 mod robots;
+mod sitemap;
+mod warc;
 
+use crate::config::SitesConfig;
 use crate::crawler::Crawler;
-use tracing::info;
+use crate::explain::Explainer;
+use db::Db;
+use std::env;
+use std::path::PathBuf;
+use tracing::{error, info};
+use url::Url;
+use utils::{shutdown_signal, RabbitMQ, CONTROL_EXCHANGE};
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
     info!("starting crawler");
 
-    let mut crawler = Crawler::new().await.unwrap();
-    crawler.crawl_loop().await;
+    let args = env::args().skip(1).collect::<Vec<_>>();
+
+    // `crawler explain <url>` prints why a url is or isn't indexed and exits, nothing is written
+    // it only reads, so it starts none of the crawler: no site import, no rabbitmq and no WARC files
+    if let [command, url] = args.as_slice() {
+        if command == "explain" {
+            let url = Url::parse(url).expect("invalid url");
+            let explainer = Explainer::new().await.unwrap();
+            if let Err(e) = explainer.explain(&url).await {
+                error!("explain failed {e}");
+            }
+            return;
+        }
+    }
+
+    // `crawler import [--requeue] <files>` indexes archived WARC responses and exits, nothing is fetched
+    // unless --requeue schedules the imported pages for live re-crawls, the crawler itself isn't started
+    if let Some(("import", args)) = args.split_first().map(|(c, a)| (c.as_str(), a)) {
+        let requeue = args.iter().any(|a| a == "--requeue");
+        let files = args
+            .iter()
+            .filter(|a| *a != "--requeue")
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        let importer = Crawler::importer().await.unwrap();
+        tokio::select! {
+            res = importer.import(&files, requeue) => {
+                if let Err(e) = res {
+                    error!("import failed {e}");
+                }
+            }
+            _ = shutdown_signal() => info!("import interrupted"),
+        }
+        info!("import stopped");
+        return;
+    }
+
+    let amq_uri = env::var("RABBITMQ").expect("RABBITMQ env not set");
+    let control = RabbitMQ::broadcast(&amq_uri, "consumer.crawler.control", CONTROL_EXCHANGE)
+        .await
+        .unwrap();
+
+    let crawler = Crawler::new().await.unwrap();
+
+    let db = Db::new(2).await.unwrap();
+    tokio::spawn(SitesConfig::watch_file(db.clone(), control.clone()));
+    let admin_control = control.clone();
+    tokio::spawn(async move {
+        if let Err(e) = admin::serve(db, admin_control).await {
+            error!("admin api stopped {e}");
+        }
+    });
+
+    if let Err(e) = crawler.seed_sitemaps().await {
+        error!("error while seeding urls from sitemaps {e}");
+    }
+    // SIGTERM / SIGINT stop the workers and return their urls to crawler_queue
+    crawler.crawl_loop(&control, shutdown_signal()).await;
+    info!("crawler stopped");
 }
@@ -0,0 +1,254 @@
+use std::time::{Duration, SystemTime};
+
+use reqwest::header::{HeaderMap, LOCATION, RETRY_AFTER};
+use reqwest::StatusCode;
+use time::format_description::well_known::Rfc2822;
+use time::OffsetDateTime;
+use url::Url;
+
+// result of a single request
+#[derive(Debug)]
+pub enum FetchOutcome {
+    Page {
+        body: String,
+        content_type: String,
+        etag: Option<String>,
+        last_modified: Option<String>,
+        headers: Vec<(String, String)>, // passed on to the parser for X-Robots-Tag and the like
+    },
+    NotModified,
+    NotIndexable,
+    TooLarge,
+    Redirect(Url),
+    // a 3xx we can't follow, retrying won't give it a location
+    BadRedirect {
+        status: StatusCode,
+        reason: String,
+    },
+    ClientError {
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+    ServerError {
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+    Timeout,
+    NetworkError(String),
+}
+
+impl FetchOutcome {
+    // 4xx and 5xx responses, retry-after only matters on 429 and 503
+    pub fn from_status(status: StatusCode, headers: &HeaderMap) -> Option<Self> {
+        let retry_after = match status {
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => headers
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_retry_after),
+            _ => None,
+        };
+
+        if status.is_client_error() {
+            Some(FetchOutcome::ClientError {
+                status,
+                retry_after,
+            })
+        } else if status.is_server_error() {
+            Some(FetchOutcome::ServerError {
+                status,
+                retry_after,
+            })
+        } else {
+            None
+        }
+    }
+
+    // 3xx responses, the location is resolved against the requested url
+    pub fn from_redirect(url: &Url, status: StatusCode, headers: &HeaderMap) -> Self {
+        let location = headers.get(LOCATION).and_then(|l| l.to_str().ok());
+        match location.map(|l| url.join(l)) {
+            Some(Ok(target)) => FetchOutcome::Redirect(target),
+            Some(Err(e)) => FetchOutcome::BadRedirect {
+                status,
+                reason: format!("redirect to invalid location {e}"),
+            },
+            None => FetchOutcome::BadRedirect {
+                status,
+                reason: "redirect without location".to_string(),
+            },
+        }
+    }
+
+    pub fn from_error(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            FetchOutcome::Timeout
+        } else {
+            FetchOutcome::NetworkError(e.to_string())
+        }
+    }
+
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            FetchOutcome::ClientError { status, .. }
+            | FetchOutcome::ServerError { status, .. }
+            | FetchOutcome::BadRedirect { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    // worth trying again later, other client errors won't change by retrying
+    pub fn is_transient(&self) -> bool {
+        match self {
+            FetchOutcome::ClientError { status, .. } => matches!(
+                *status,
+                StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS
+            ),
+            FetchOutcome::ServerError { .. }
+            | FetchOutcome::Timeout
+            | FetchOutcome::NetworkError(_) => true,
+            _ => false,
+        }
+    }
+
+    // the page was removed, its document should no longer be found
+    pub fn is_gone(&self) -> bool {
+        matches!(
+            self.status(),
+            Some(StatusCode::NOT_FOUND) | Some(StatusCode::GONE)
+        )
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            FetchOutcome::ClientError { retry_after, .. }
+            | FetchOutcome::ServerError { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    pub fn reason(&self) -> String {
+        match self {
+            FetchOutcome::ClientError { status, .. } | FetchOutcome::ServerError { status, .. } => {
+                status.to_string()
+            }
+            FetchOutcome::BadRedirect { status, reason } => format!("{status} {reason}"),
+            FetchOutcome::Timeout => "request timed out".to_string(),
+            FetchOutcome::NetworkError(e) => e.clone(),
+            _ => String::new(),
+        }
+    }
+}
+
+// what the host worker should do with a url after crawling it
+#[derive(Debug)]
+pub enum Crawled {
+    // nothing left to do, drop it from the queue
+    Done,
+    // crawled, fetch again after the refresh interval
+    Recrawl(Duration),
+    // transient failure, retry after the server's retry-after or our backoff
+    Retry {
+        after: Option<Duration>,
+        reason: String,
+    },
+    // permanent failure, dead-letter it
+    Failed {
+        status: Option<StatusCode>,
+        reason: String,
+    },
+}
+
+// either delay-seconds or an http-date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = OffsetDateTime::parse(value, &Rfc2822).ok()?;
+    Some(
+        SystemTime::from(date)
+            .duration_since(SystemTime::now())
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_from_status() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+
+        let cases = [
+            (StatusCode::OK, None),
+            (StatusCode::NOT_FOUND, Some((false, true, None))),
+            (StatusCode::GONE, Some((false, true, None))),
+            (StatusCode::FORBIDDEN, Some((false, false, None))),
+            (StatusCode::REQUEST_TIMEOUT, Some((true, false, None))),
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                Some((true, false, Some(120))),
+            ),
+            (StatusCode::INTERNAL_SERVER_ERROR, Some((true, false, None))),
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Some((true, false, Some(120))),
+            ),
+        ];
+
+        for (status, expected) in cases {
+            let outcome = FetchOutcome::from_status(status, &headers);
+            let got = outcome.map(|o| {
+                (
+                    o.is_transient(),
+                    o.is_gone(),
+                    o.retry_after().map(|d| d.as_secs()),
+                )
+            });
+            assert_eq!(got, expected, "{status}");
+        }
+    }
+
+    #[test]
+    fn test_from_redirect() {
+        let url = Url::parse("https://x/docs/a").unwrap();
+        let status = StatusCode::MOVED_PERMANENTLY;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(LOCATION, HeaderValue::from_static("../b"));
+        let outcome = FetchOutcome::from_redirect(&url, status, &headers);
+        assert!(matches!(outcome, FetchOutcome::Redirect(t) if t.as_str() == "https://x/b"));
+
+        // permanent failures, dead-lettered with the status
+        let outcome = FetchOutcome::from_redirect(&url, status, &HeaderMap::new());
+        assert!(!outcome.is_transient());
+        assert!(!outcome.is_gone());
+        assert_eq!(outcome.status(), Some(status));
+        assert_eq!(
+            outcome.reason(),
+            "301 Moved Permanently redirect without location"
+        );
+
+        headers.insert(LOCATION, HeaderValue::from_static("http://[::1"));
+        let outcome = FetchOutcome::from_redirect(&url, status, &headers);
+        assert!(matches!(outcome, FetchOutcome::BadRedirect { .. }));
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after(" 30 "), Some(Duration::from_secs(30)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+
+        let later = parse_retry_after("Thu, 01 Jan 2099 00:00:00 GMT").unwrap();
+        assert!(later > Duration::from_secs(60 * 60 * 24 * 365));
+
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
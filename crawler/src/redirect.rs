@@ -0,0 +1,143 @@
+// redirects we follow by hand so the chain can be recorded, see Crawler::crawl
+
+pub const MAX_REDIRECTS: usize = 10;
+// refreshes slower than this are pages in their own right, not redirects
+const MAX_META_REFRESH_DELAY: f64 = 5.0;
+
+// target of a <meta http-equiv="refresh" content="0; url=..."> in the page, unresolved
+pub fn meta_refresh(body: &str) -> Option<String> {
+    let lower = body.to_ascii_lowercase();
+    // the tag belongs in <head>, don't scan the whole body
+    let end = lower.find("</head").unwrap_or(lower.len());
+
+    let mut pos = 0;
+    while let Some(start) = lower.get(pos..end).and_then(|l| l.find("<meta")) {
+        let start = pos + start + "<meta".len();
+        let Some(len) = body[start..].find('>') else {
+            break;
+        };
+        pos = start + len;
+
+        let attrs = attributes(&body[start..pos]);
+        let is_refresh = attrs
+            .iter()
+            .any(|(k, v)| k == "http-equiv" && v.eq_ignore_ascii_case("refresh"));
+        if !is_refresh {
+            continue;
+        }
+
+        let content = attrs.iter().find(|(k, _)| k == "content")?;
+        return parse_refresh(&content.1);
+    }
+
+    None
+}
+
+// "5; url=/next", "0;URL='/next'" or "0, /next"
+fn parse_refresh(content: &str) -> Option<String> {
+    let (delay, target) = content.split_once([';', ','])?;
+    let delay = delay.trim().parse::<f64>().ok()?;
+    if delay > MAX_META_REFRESH_DELAY {
+        return None;
+    }
+
+    let mut target = target.trim();
+    if target
+        .get(..3)
+        .is_some_and(|p| p.eq_ignore_ascii_case("url"))
+    {
+        target = target[3..].trim_start().strip_prefix('=')?.trim();
+    }
+    let target = target.trim_matches(|c| c == '\'' || c == '"').trim();
+
+    if target.is_empty() {
+        return None;
+    }
+    Some(target.to_string())
+}
+
+// attribute names lowercased, values unquoted
+pub fn attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attrs = vec![];
+    let mut chars = tag.trim_end_matches('/').chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let name = std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace() && *c != '='))
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if name.is_empty() {
+            // stray '=' or end of tag
+            if chars.next().is_none() {
+                break;
+            }
+            continue;
+        }
+
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.next_if_eq(&'=').is_none() {
+            attrs.push((name, String::new()));
+            continue;
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let value = match chars.next_if(|c| *c == '"' || *c == '\'') {
+            Some(quote) => {
+                let v = std::iter::from_fn(|| chars.next_if(|c| *c != quote)).collect();
+                chars.next();
+                v
+            }
+            None => std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace())).collect(),
+        };
+        attrs.push((name, value));
+    }
+
+    attrs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meta_refresh() {
+        let cases = [
+            (
+                r#"<html><head><meta http-equiv="refresh" content="0; url=https://x/new"></head></html>"#,
+                Some("https://x/new"),
+            ),
+            (
+                r#"<HEAD><META HTTP-EQUIV=Refresh CONTENT="3;URL='/next?a=1'"/></HEAD>"#,
+                Some("/next?a=1"),
+            ),
+            (
+                r#"<head><meta charset="utf-8"><meta content="0, other.html" http-equiv='refresh'></head>"#,
+                Some("other.html"),
+            ),
+            // slow refreshes and plain reloads are not redirects
+            (
+                r#"<head><meta http-equiv="refresh" content="30; url=/later"></head>"#,
+                None,
+            ),
+            (
+                r#"<head><meta http-equiv="refresh" content="60"></head>"#,
+                None,
+            ),
+            (
+                r#"<head><meta name="refresh" content="0; url=/x"></head>"#,
+                None,
+            ),
+            // only the head is scanned
+            (
+                r#"<head></head><body><meta http-equiv="refresh" content="0; url=/x"></body>"#,
+                None,
+            ),
+            ("<html><head><title>no refresh</title></head></html>", None),
+        ];
+
+        for (body, expected) in cases {
+            assert_eq!(meta_refresh(body).as_deref(), expected, "{body}");
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use sqlx::Acquire;
+
+use db::Db;
+
+// validators and refresh interval of a crawled url, used for conditional and adaptive re-crawls
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrawlState {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_hash: Option<String>,
+    refresh_interval: u32, // seconds
+}
+
+impl Default for CrawlState {
+    fn default() -> Self {
+        CrawlState {
+            etag: None,
+            last_modified: None,
+            content_hash: None,
+            refresh_interval: Self::DEFAULT_INTERVAL,
+        }
+    }
+}
+
+impl CrawlState {
+    const DEFAULT_INTERVAL: u32 = 60 * 60 * 24; // 1 day
+    const MIN_INTERVAL: u32 = 60 * 60; // 1 hour
+    const MAX_INTERVAL: u32 = 60 * 60 * 24 * 30; // 30 days
+
+    // how far the adaptive interval may drift from the interval a sitemap's changefreq suggests
+    const HINT_SPREAD: u32 = 4;
+
+    // a url seen for the first time starts at the interval its sitemap suggests, if any
+    pub fn new(hint: Option<Duration>) -> Self {
+        let mut state = CrawlState::default();
+        if let Some(hint) = hint {
+            state.refresh_interval = Self::hint_secs(hint);
+        }
+        state
+    }
+
+    fn hint_secs(hint: Duration) -> u32 {
+        u32::try_from(hint.as_secs())
+            .unwrap_or(u32::MAX)
+            .clamp(Self::MIN_INTERVAL, Self::MAX_INTERVAL)
+    }
+
+    // keep the interval within HINT_SPREAD of the sitemap's changefreq, the page's own history moves it inside that
+    pub fn follow_hint(&mut self, hint: Option<Duration>) {
+        let Some(hint) = hint else {
+            return;
+        };
+        let hint = Self::hint_secs(hint);
+        let low = (hint / Self::HINT_SPREAD).max(Self::MIN_INTERVAL);
+        let high = hint
+            .saturating_mul(Self::HINT_SPREAD)
+            .min(Self::MAX_INTERVAL);
+        self.refresh_interval = self.refresh_interval.clamp(low, high);
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.refresh_interval as u64)
+    }
+
+    pub fn interval_secs(&self) -> u32 {
+        self.refresh_interval
+    }
+
+    // page did not change since the last crawl, back off
+    pub fn unchanged(&mut self) {
+        self.refresh_interval = self
+            .refresh_interval
+            .saturating_mul(2)
+            .clamp(Self::MIN_INTERVAL, Self::MAX_INTERVAL);
+    }
+
+    // page changed since the last crawl, come back sooner
+    // returns false if this is the first time we have seen its content
+    pub fn changed(&mut self, content_hash: String) -> bool {
+        let seen_before = self.content_hash.replace(content_hash).is_some();
+        if seen_before {
+            self.refresh_interval =
+                (self.refresh_interval / 2).clamp(Self::MIN_INTERVAL, Self::MAX_INTERVAL);
+        }
+        seen_before
+    }
+
+    pub async fn load(db: &Db, url: &str) -> Result<Option<Self>> {
+        let mut pool = db.get_pg().await?;
+
+        let state = sqlx::query_as::<_, (Option<String>, Option<String>, Option<String>, i32)>(
+            r#"
+            SELECT etag, last_modified, content_hash, refresh_interval
+            FROM crawl_state
+            WHERE url = $1
+            "#,
+        )
+        .bind(url)
+        .fetch_optional(pool.acquire().await?)
+        .await?;
+
+        Ok(state.map(
+            |(etag, last_modified, content_hash, refresh_interval)| CrawlState {
+                etag,
+                last_modified,
+                content_hash,
+                refresh_interval: refresh_interval as u32,
+            },
+        ))
+    }
+
+    pub async fn save(&self, db: &Db, url: &str, changed: bool) -> Result<()> {
+        let mut pool = db.get_pg().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO crawl_state
+                (url, etag, last_modified, content_hash, refresh_interval, last_crawled_at, last_changed_at)
+            VALUES ($1, $2, $3, $4, $5, now(), CASE WHEN $6 THEN now() END)
+            ON CONFLICT (url) DO UPDATE SET
+                etag = EXCLUDED.etag,
+                last_modified = EXCLUDED.last_modified,
+                content_hash = EXCLUDED.content_hash,
+                refresh_interval = EXCLUDED.refresh_interval,
+                last_crawled_at = EXCLUDED.last_crawled_at,
+                last_changed_at = COALESCE(EXCLUDED.last_changed_at, crawl_state.last_changed_at)
+            "#,
+        )
+        .bind(url)
+        .bind(&self.etag)
+        .bind(&self.last_modified)
+        .bind(&self.content_hash)
+        .bind(self.refresh_interval as i32)
+        .bind(changed)
+        .execute(pool.acquire().await?)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adaptive_interval() {
+        let mut state = CrawlState::default();
+        let day = CrawlState::DEFAULT_INTERVAL;
+
+        // first crawl keeps the default interval
+        assert!(!state.changed("a".to_string()));
+        assert_eq!(state.interval_secs(), day);
+
+        state.unchanged();
+        assert_eq!(state.interval_secs(), day * 2);
+
+        assert!(state.changed("b".to_string()));
+        assert_eq!(state.interval_secs(), day);
+        assert_eq!(state.content_hash.as_deref(), Some("b"));
+
+        for _ in 0..20 {
+            state.unchanged();
+        }
+        assert_eq!(state.interval_secs(), CrawlState::MAX_INTERVAL);
+
+        for i in 0..20 {
+            state.changed(i.to_string());
+        }
+        assert_eq!(state.interval_secs(), CrawlState::MIN_INTERVAL);
+    }
+
+    #[test]
+    fn test_changefreq_hint() {
+        let hour = Duration::from_secs(60 * 60);
+        let week = hour * 24 * 7;
+
+        assert_eq!(CrawlState::new(None), CrawlState::default());
+        assert_eq!(CrawlState::new(Some(week)).interval(), week);
+        // always and never are clamped like any other interval
+        assert_eq!(
+            CrawlState::new(Some(Duration::ZERO)).interval_secs(),
+            CrawlState::MIN_INTERVAL
+        );
+        assert_eq!(
+            CrawlState::new(Some(Duration::MAX)).interval_secs(),
+            CrawlState::MAX_INTERVAL
+        );
+
+        // a daily page that never changes backs off to 4 days, not 30
+        let mut state = CrawlState::new(Some(hour * 24));
+        for _ in 0..20 {
+            state.unchanged();
+            state.follow_hint(Some(hour * 24));
+        }
+        assert_eq!(state.interval(), hour * 24 * 4);
+
+        // a weekly page that changes all the time is still crawled at most every 42 hours
+        let mut state = CrawlState::new(Some(week));
+        for i in 0..20 {
+            state.changed(i.to_string());
+            state.follow_hint(Some(week));
+        }
+        assert_eq!(state.interval(), week / 4);
+
+        state.follow_hint(None);
+        assert_eq!(state.interval(), week / 4);
+    }
+}
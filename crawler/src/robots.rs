@@ -1,74 +1,536 @@
+use std::time::Duration;
+
 use anyhow::Result;
-use reqwest::{get, StatusCode};
-use std::collections::HashMap;
-use tracing::info;
+use reqwest::header::USER_AGENT;
+use reqwest::redirect::Policy;
+use reqwest::Client;
+use tracing::{info, warn};
 use url::Url;
 
+use crate::config::FOXEYE_USER_AGENT;
+
+// robots.txt parsing and matching as described in RFC 9309
+// https://www.rfc-editor.org/rfc/rfc9309.html
+
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Group {
+    user_agents: Vec<String>, // lowercased product tokens, "*" for the catch-all group
+    rules: Vec<Rule>,
+    crawl_delay: Option<f64>, // seconds, non-standard but widely used
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct RobotsTxt {
-    allow_rules: HashMap<String, Vec<String>>,
-    disallow_rules: HashMap<String, Vec<String>>,
+    groups: Vec<Group>,
+    sitemaps: Vec<Url>,
+    disallow_all: bool,
 }
 
 impl RobotsTxt {
+    // crawlers must parse at least 500 KiB (RFC 9309 2.5)
+    const MAX_SIZE: usize = 500 * 1024;
+    // crawlers should follow at least five consecutive redirects (RFC 9309 2.3.1.2)
+    const MAX_REDIRECTS: usize = 5;
+
     pub async fn from_url(url: Url) -> Result<Self> {
         info!("gettig robots.txt for {}", url);
-        let url = url.join("robots.txt")?;
-        let response = get(url).await?;
+        let url = url.join("/robots.txt")?;
+
+        let client = Client::builder()
+            .redirect(Policy::limited(Self::MAX_REDIRECTS))
+            .build()?;
+
+        let response = match client
+            .get(url.clone())
+            .header(USER_AGENT, FOXEYE_USER_AGENT)
+            .send()
+            .await
+        {
+            Ok(res) => res,
+            Err(e) => {
+                warn!("robots.txt for {url} is unreachable, disallowing all: {e}");
+                return Ok(RobotsTxt::disallow_all());
+            }
+        };
+
+        let status = response.status();
 
-        if response.status() == StatusCode::NOT_FOUND {
+        // 4xx (unavailable): crawler may access any resource
+        if status.is_client_error() {
+            info!("robots.txt for {url} returned {status}, allowing all");
             return Ok(RobotsTxt::default());
         }
 
-        let robots = response.text().await?;
+        // 5xx (unreachable) or anything else unexpected: assume complete disallow
+        if !status.is_success() {
+            warn!("robots.txt for {url} returned {status}, disallowing all");
+            return Ok(RobotsTxt::disallow_all());
+        }
+
+        let body = match response.bytes().await {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("failed to read robots.txt body for {url}, disallowing all: {e}");
+                return Ok(RobotsTxt::disallow_all());
+            }
+        };
+        let body = &body[..body.len().min(Self::MAX_SIZE)];
 
-        let mut allow_rules = HashMap::new();
-        let mut disallow_rules = HashMap::new();
-        let mut current_user_agent = String::from("*");
+        Ok(RobotsTxt::parse(&String::from_utf8_lossy(body)))
+    }
+
+    pub fn disallow_all() -> Self {
+        RobotsTxt {
+            groups: vec![],
+            sitemaps: vec![],
+            disallow_all: true,
+        }
+    }
+
+    pub fn parse(robots: &str) -> Self {
+        let mut groups: Vec<Group> = vec![];
+        let mut sitemaps = vec![];
+        // true while consecutive user-agent lines are still opening the current group
+        let mut in_group_start = false;
 
         for line in robots.lines() {
-            if line.starts_with("User-agent:") {
-                let user_agent = line.split(':').nth(1).unwrap_or("").trim().to_string();
-                current_user_agent.clone_from(&user_agent);
-
-                allow_rules
-                    .entry(user_agent.clone())
-                    .or_insert_with(Vec::new);
-                disallow_rules.entry(user_agent).or_insert_with(Vec::new);
-            } else if line.starts_with("Allow:") {
-                if let Some(path) = line.split(':').nth(1) {
-                    allow_rules
-                        .entry(current_user_agent.clone())
-                        .or_insert_with(Vec::new)
-                        .push(path.trim().to_string());
+            let line = match line.find('#') {
+                Some(i) => &line[..i],
+                None => line,
+            };
+
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let key = key.trim().to_ascii_lowercase();
+            let value = value.trim();
+
+            // any other record ends the run of user-agent lines, the next user-agent starts a new group
+            if key != "user-agent" {
+                in_group_start = false;
+            }
+
+            match key.as_str() {
+                "user-agent" => {
+                    if !in_group_start {
+                        groups.push(Group::default());
+                        in_group_start = true;
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        group.user_agents.push(product_token(value));
+                    }
                 }
-            } else if line.starts_with("Disallow:") {
-                if let Some(path) = line.split(':').nth(1) {
-                    disallow_rules
-                        .entry(current_user_agent.clone())
-                        .or_insert_with(Vec::new)
-                        .push(path.trim().to_string());
+                "allow" | "disallow" => {
+                    // rules outside of a group are ignored
+                    let Some(group) = groups.last_mut() else {
+                        continue;
+                    };
+
+                    // an empty pattern matches nothing
+                    if value.is_empty() {
+                        continue;
+                    }
+
+                    group.rules.push(Rule {
+                        allow: key == "allow",
+                        pattern: normalize(value),
+                    });
+                }
+                "crawl-delay" => {
+                    let Some(group) = groups.last_mut() else {
+                        continue;
+                    };
+
+                    match value.parse::<f64>() {
+                        Ok(delay) if delay.is_finite() && delay >= 0.0 => {
+                            group.crawl_delay = Some(delay)
+                        }
+                        _ => warn!("invalid crawl-delay in robots.txt: {value}"),
+                    }
+                }
+                "sitemap" => {
+                    // sitemap is not tied to any group and must be an absolute url
+                    match Url::parse(value) {
+                        Ok(url) => sitemaps.push(url),
+                        Err(e) => warn!("invalid sitemap url in robots.txt {value}: {e}"),
+                    }
+                }
+                _ => {
+                    // other records do not end a group, rules after them still belong to it
                 }
             }
         }
 
-        Ok(RobotsTxt {
-            allow_rules,
-            disallow_rules,
-        })
+        RobotsTxt {
+            groups,
+            sitemaps,
+            disallow_all: false,
+        }
+    }
+
+    // every group matching the product token, falling back to the "*" groups
+    fn groups_for(&self, user_agent: &str) -> Vec<&Group> {
+        let token = product_token(user_agent);
+
+        let matched = self.groups.iter().any(|g| g.user_agents.contains(&token));
+        let token = if matched { token.as_str() } else { "*" };
+
+        self.groups
+            .iter()
+            .filter(|g| g.user_agents.iter().any(|u| u == token))
+            .collect()
+    }
+
+    // merged rules of all matching groups
+    fn rules_for(&self, user_agent: &str) -> Vec<&Rule> {
+        self.groups_for(user_agent)
+            .into_iter()
+            .flat_map(|g| g.rules.iter())
+            .collect()
     }
 
+    // strictest crawl-delay among the matching groups
+    pub fn crawl_delay(&self, user_agent: &str) -> Option<Duration> {
+        self.groups_for(user_agent)
+            .into_iter()
+            .filter_map(|g| g.crawl_delay)
+            .reduce(f64::max)
+            .map(Duration::from_secs_f64)
+    }
+
+    pub fn sitemaps(&self) -> &[Url] {
+        &self.sitemaps
+    }
+
+    // `path` is the path and query of the url, e.g. "/search?q=foxeye"
     pub fn is_allowed(&self, user_agent: &str, path: &str) -> bool {
-        if let Some(allow_paths) = self.allow_rules.get(user_agent) {
-            if allow_paths.iter().any(|p| path.starts_with(p)) {
+        if self.disallow_all {
+            return false;
+        }
+
+        self.best_rule(user_agent, path).is_none_or(|r| r.allow)
+    }
+
+    // robots.txt couldn't be fetched, see from_url
+    pub fn disallows_all(&self) -> bool {
+        self.disallow_all
+    }
+
+    // the rule deciding `path` as it appears in robots.txt, none if no rule matches it
+    pub fn matching_rule(&self, user_agent: &str, path: &str) -> Option<String> {
+        self.best_rule(user_agent, path).map(|r| {
+            let directive = if r.allow { "Allow" } else { "Disallow" };
+            format!("{directive}: {}", r.pattern)
+        })
+    }
+
+    fn best_rule(&self, user_agent: &str, path: &str) -> Option<&Rule> {
+        // robots.txt itself is always allowed
+        if path == "/robots.txt" {
+            return None;
+        }
+
+        let path = normalize(path);
+
+        // longest match wins, allow wins ties
+        let mut best: Option<&Rule> = None;
+        for rule in self.rules_for(user_agent) {
+            if !matches(&rule.pattern, &path) {
+                continue;
+            }
+
+            best = match best {
+                Some(b) if b.pattern.len() > rule.pattern.len() => Some(b),
+                Some(b) if b.pattern.len() == rule.pattern.len() && b.allow => Some(b),
+                _ => Some(rule),
+            };
+        }
+
+        best
+    }
+}
+
+// product token is the leading run of [a-zA-Z_-] characters, compared case-insensitively
+fn product_token(user_agent: &str) -> String {
+    let user_agent = user_agent.trim();
+    if user_agent.starts_with('*') {
+        return String::from("*");
+    }
+
+    user_agent
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic() || *c == '_' || *c == '-')
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+// percent-encode non ascii octets and uppercase existing escapes so patterns and paths compare equal
+fn normalize(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut out = String::with_capacity(path.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let b = bytes[i];
+        if b == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+        {
+            out.push('%');
+            out.push(bytes[i + 1].to_ascii_uppercase() as char);
+            out.push(bytes[i + 2].to_ascii_uppercase() as char);
+            i += 3;
+            continue;
+        }
+
+        if b.is_ascii() {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+        i += 1;
+    }
+
+    out
+}
+
+// `*` matches any sequence of characters, a trailing `$` anchors the pattern to the end of the path
+fn matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(p) => (p, true),
+        None => (pattern, false),
+    };
+
+    let pattern = pattern.as_bytes();
+    let path = path.as_bytes();
+
+    let (mut p, mut s) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    loop {
+        if p == pattern.len() {
+            if !anchored || s == path.len() {
                 return true;
             }
+        } else if pattern[p] == b'*' {
+            star = Some((p, s));
+            p += 1;
+            continue;
+        } else if s < path.len() && pattern[p] == path[s] {
+            p += 1;
+            s += 1;
+            continue;
         }
-        if let Some(disallow_paths) = self.disallow_rules.get(user_agent) {
-            if disallow_paths.iter().any(|p| path.starts_with(p)) {
-                return false;
+
+        // backtrack to the last star and let it swallow one more character
+        match star {
+            Some((sp, ss)) if ss < path.len() => {
+                star = Some((sp, ss + 1));
+                p = sp + 1;
+                s = ss + 1;
             }
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UA: &str = "Foxeye Search";
+
+    #[test]
+    fn test_is_allowed() {
+        // (robots.txt, path, expected)
+        let cases: &[(&str, &str, bool)] = &[
+            // no rules
+            ("", "/", true),
+            ("User-agent: *\n", "/anything", true),
+            // plain prefix matching
+            ("User-agent: *\nDisallow: /", "/", false),
+            ("User-agent: *\nDisallow: /private", "/private/page", false),
+            ("User-agent: *\nDisallow: /private", "/public", true),
+            ("User-agent: *\nDisallow:", "/anything", true),
+            // fall back to "*" when no group matches our product token
+            ("User-agent: Googlebot\nDisallow: /\n\nUser-agent: *\nDisallow: /tmp", "/page", true),
+            ("User-agent: Googlebot\nDisallow: /\n\nUser-agent: *\nDisallow: /tmp", "/tmp/a", false),
+            // our own group takes precedence over "*"
+            ("User-agent: *\nDisallow: /\n\nUser-agent: Foxeye\nDisallow: /tmp", "/page", true),
+            ("User-agent: *\nAllow: /\n\nUser-agent: foxeye\nDisallow: /", "/page", false),
+            // product token matching is case-insensitive and ignores version/comments
+            ("User-agent: FOXEYE/1.0\nDisallow: /x", "/x", false),
+            ("User-agent: Foxeye Search\nDisallow: /x", "/x", false),
+            ("User-agent: Foxeyebot\nDisallow: /x", "/x", true),
+            // directives are case-insensitive and tolerate whitespace and comments
+            ("user-AGENT : *\n  disallow :  /x  # comment", "/x/y", false),
+            ("# only a comment\nUser-agent: *\nDisallow: /x # trailing", "/x", false),
+            // consecutive user-agent lines share a group
+            ("User-agent: a\nUser-agent: foxeye\nDisallow: /shared", "/shared", false),
+            // groups for the same agent are merged
+            ("User-agent: foxeye\nDisallow: /a\n\nUser-agent: b\nDisallow: /b\n\nUser-agent: foxeye\nDisallow: /c", "/c", false),
+            ("User-agent: foxeye\nDisallow: /a\n\nUser-agent: b\nDisallow: /b\n\nUser-agent: foxeye\nDisallow: /c", "/b", true),
+            // rules before any user-agent line are ignored
+            ("Disallow: /\nUser-agent: *\nAllow: /", "/page", true),
+            // non-group records do not end a group
+            ("User-agent: *\nCrawl-delay: 5\nDisallow: /x", "/x", false),
+            // but they end the user-agent lines opening it, a user-agent after them starts a new group
+            ("User-agent: a\nSitemap: https://x/s.xml\nUser-agent: foxeye\nDisallow: /x", "/x", false),
+            ("User-agent: foxeye\nSitemap: https://x/s.xml\nUser-agent: a\nDisallow: /x", "/x", true),
+            ("User-agent: foxeye\nCrawl-delay: 1\nUser-agent: a\nDisallow: /x", "/x", true),
+            // longest match wins regardless of order
+            ("User-agent: *\nAllow: /p\nDisallow: /page", "/page", false),
+            ("User-agent: *\nDisallow: /page\nAllow: /page/public", "/page/public/1", true),
+            ("User-agent: *\nAllow: /\nDisallow: /admin", "/admin/x", false),
+            // allow wins ties
+            ("User-agent: *\nDisallow: /page\nAllow: /page", "/page", true),
+            // wildcards
+            ("User-agent: *\nDisallow: /*.php", "/index.php", false),
+            ("User-agent: *\nDisallow: /*.php", "/dir/index.php?x=1", false),
+            ("User-agent: *\nDisallow: /*.php", "/index.html", true),
+            ("User-agent: *\nDisallow: /*.php$", "/index.php", false),
+            ("User-agent: *\nDisallow: /*.php$", "/index.php?x=1", true),
+            ("User-agent: *\nDisallow: /fish*", "/fishheads", false),
+            ("User-agent: *\nDisallow: /*/history", "/w/page/history", false),
+            ("User-agent: *\nDisallow: /w/*action=history", "/w/index.php?title=x&action=history", false),
+            ("User-agent: *\nDisallow: /$", "/", false),
+            ("User-agent: *\nDisallow: /$", "/page", true),
+            ("User-agent: *\nDisallow: /\nAllow: /$", "/", true),
+            ("User-agent: *\nDisallow: /\nAllow: /$", "/page", false),
+            // percent-encoding is normalised on both sides
+            ("User-agent: *\nDisallow: /foo/bar%3c", "/foo/bar%3C", false),
+            ("User-agent: *\nDisallow: /\u{00e4}", "/%c3%a4", false),
+            // robots.txt itself is always allowed
+            ("User-agent: *\nDisallow: /", "/robots.txt", true),
+        ];
+
+        for (i, (robots, path, expected)) in cases.iter().enumerate() {
+            let robots_txt = RobotsTxt::parse(robots);
+            assert_eq!(
+                robots_txt.is_allowed(UA, path),
+                *expected,
+                "case {i}: {path:?} against {robots:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_matching_rule() {
+        let robots = RobotsTxt::parse("User-agent: *\nDisallow: /page\nAllow: /page/public");
+        let cases = [
+            ("/page/1", Some("Disallow: /page")),
+            ("/page/public/1", Some("Allow: /page/public")),
+            ("/other", None),
+            ("/robots.txt", None),
+        ];
+
+        for (path, expected) in cases {
+            assert_eq!(
+                robots.matching_rule(UA, path).as_deref(),
+                expected,
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_disallow_all() {
+        let robots = RobotsTxt::disallow_all();
+        assert!(!robots.is_allowed(UA, "/"));
+        assert!(!robots.is_allowed(UA, "/robots.txt"));
+        assert!(RobotsTxt::default().is_allowed(UA, "/anything"));
+    }
+
+    #[test]
+    fn test_crawl_delay() {
+        // (robots.txt, expected delay in seconds)
+        let cases: &[(&str, Option<f64>)] = &[
+            ("", None),
+            ("User-agent: *\nDisallow: /", None),
+            ("User-agent: *\nCrawl-delay: 5", Some(5.0)),
+            ("User-agent: *\ncrawl-DELAY: 0.5", Some(0.5)),
+            ("User-agent: *\nCrawl-delay: soon", None),
+            ("User-agent: *\nCrawl-delay: -1", None),
+            // delay outside of a group is ignored
+            ("Crawl-delay: 5\nUser-agent: *\nDisallow: /x", None),
+            // our own group takes precedence over "*"
+            (
+                "User-agent: *\nCrawl-delay: 10\n\nUser-agent: foxeye\nCrawl-delay: 2",
+                Some(2.0),
+            ),
+            (
+                "User-agent: googlebot\nCrawl-delay: 10\n\nUser-agent: *\nCrawl-delay: 3",
+                Some(3.0),
+            ),
+            // the strictest delay of merged groups wins
+            (
+                "User-agent: foxeye\nCrawl-delay: 2\n\nUser-agent: foxeye\nCrawl-delay: 4",
+                Some(4.0),
+            ),
+        ];
+
+        for (robots, expected) in cases {
+            let delay = RobotsTxt::parse(robots).crawl_delay(UA);
+            assert_eq!(
+                delay,
+                expected.map(Duration::from_secs_f64),
+                "crawl-delay in {robots:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_sitemaps() {
+        let robots = RobotsTxt::parse(
+            "Sitemap: https://example.com/sitemap.xml\n\
+             User-agent: *\n\
+             Disallow: /x\n\
+             sitemap: https://example.com/news.xml # comment\n\
+             Sitemap: /relative.xml\n\
+             Disallow: /y",
+        );
+
+        let sitemaps = robots
+            .sitemaps()
+            .iter()
+            .map(|u| u.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sitemaps,
+            vec![
+                "https://example.com/sitemap.xml",
+                "https://example.com/news.xml"
+            ]
+        );
+        // sitemap lines do not end the group
+        assert!(!robots.is_allowed(UA, "/y"));
+    }
+
+    #[test]
+    fn test_matches() {
+        // (pattern, path, expected)
+        let cases: &[(&str, &str, bool)] = &[
+            ("/", "/", true),
+            ("/a", "/", false),
+            ("*", "", true),
+            ("/*", "/a/b", true),
+            ("/a*c", "/abbbc", true),
+            ("/a*c", "/abbbd", false),
+            ("/a*c$", "/abcc", true),
+            ("/a*c$", "/abcd", false),
+            ("/a**b", "/axb", true),
+            ("$", "", true),
+        ];
+
+        for (pattern, path, expected) in cases {
+            assert_eq!(matches(pattern, path), *expected, "{pattern:?} on {path:?}");
         }
-        true
     }
 }
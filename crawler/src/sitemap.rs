@@ -0,0 +1,441 @@
+use std::collections::HashSet;
+use std::io::Read;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use flate2::read::GzDecoder;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use reqwest::header::USER_AGENT;
+use reqwest::Client;
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{Date, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+use tracing::{info, warn};
+use url::Url;
+
+use crate::body::read_limited;
+use crate::config::FOXEYE_USER_AGENT;
+
+// sitemap protocol: https://www.sitemaps.org/protocol.html
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChangeFreq {
+    Always,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    Never,
+}
+
+impl ChangeFreq {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "always" => Some(ChangeFreq::Always),
+            "hourly" => Some(ChangeFreq::Hourly),
+            "daily" => Some(ChangeFreq::Daily),
+            "weekly" => Some(ChangeFreq::Weekly),
+            "monthly" => Some(ChangeFreq::Monthly),
+            "yearly" => Some(ChangeFreq::Yearly),
+            "never" => Some(ChangeFreq::Never),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeFreq::Always => "always",
+            ChangeFreq::Hourly => "hourly",
+            ChangeFreq::Daily => "daily",
+            ChangeFreq::Weekly => "weekly",
+            ChangeFreq::Monthly => "monthly",
+            ChangeFreq::Yearly => "yearly",
+            ChangeFreq::Never => "never",
+        }
+    }
+
+    // how often the site says the page changes, a hint for the refresh interval and not a promise
+    pub fn interval(&self) -> Duration {
+        let hour = 60 * 60;
+        Duration::from_secs(match self {
+            ChangeFreq::Always => 0,
+            ChangeFreq::Hourly => hour,
+            ChangeFreq::Daily => hour * 24,
+            ChangeFreq::Weekly => hour * 24 * 7,
+            ChangeFreq::Monthly => hour * 24 * 30,
+            ChangeFreq::Yearly => hour * 24 * 365,
+            ChangeFreq::Never => u64::MAX,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SitemapUrl {
+    pub url: Url,
+    pub lastmod: Option<PrimitiveDateTime>, // utc
+    pub priority: f32,
+    pub changefreq: Option<ChangeFreq>,
+}
+
+impl SitemapUrl {
+    // true if the sitemap says the page changed after `crawled_at`, or can't tell
+    pub fn modified_since(&self, crawled_at: Option<PrimitiveDateTime>) -> bool {
+        match (self.lastmod, crawled_at) {
+            (Some(lastmod), Some(crawled_at)) => lastmod > crawled_at,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sitemap {
+    UrlSet(Vec<SitemapUrl>),
+    Index(Vec<Url>),
+}
+
+impl Sitemap {
+    const DEFAULT_PRIORITY: f32 = 0.5;
+    // a single sitemap may hold 50,000 urls and be 50 MB uncompressed
+    const MAX_URLS: usize = 50_000;
+    const MAX_SIZE: u64 = 50 * 1024 * 1024;
+    // upper bound on sitemaps fetched for a single seed, guards against index loops
+    const MAX_SITEMAPS: usize = 100;
+
+    pub fn parse(body: &[u8]) -> Result<Self> {
+        let body = String::from_utf8_lossy(body);
+        let mut reader = Reader::from_str(&body);
+        reader.trim_text(true);
+
+        let mut is_index: Option<bool> = None;
+        let mut path: Vec<String> = vec![];
+
+        let mut urls = vec![];
+        let mut sitemaps = vec![];
+
+        let mut loc: Option<String> = None;
+        let mut lastmod: Option<String> = None;
+        let mut priority: Option<String> = None;
+        let mut changefreq: Option<String> = None;
+
+        loop {
+            match reader.read_event()? {
+                Event::Start(e) => {
+                    let name = String::from_utf8_lossy(e.local_name().as_ref()).to_lowercase();
+                    if is_index.is_none() {
+                        is_index = match name.as_str() {
+                            "urlset" => Some(false),
+                            "sitemapindex" => Some(true),
+                            _ => return Err(anyhow!("unknown sitemap root element <{name}>")),
+                        };
+                    }
+                    path.push(name);
+                }
+                Event::End(_) => {
+                    let Some(name) = path.pop() else {
+                        continue;
+                    };
+
+                    if name != "url" && name != "sitemap" {
+                        continue;
+                    }
+
+                    let (l, m, p, c) = (
+                        loc.take(),
+                        lastmod.take(),
+                        priority.take(),
+                        changefreq.take(),
+                    );
+
+                    let Some(l) = l.and_then(|l| Url::parse(l.trim()).ok()) else {
+                        warn!("sitemap entry without a valid <loc>, skipping");
+                        continue;
+                    };
+
+                    if name == "sitemap" {
+                        sitemaps.push(l);
+                    } else if urls.len() < Self::MAX_URLS {
+                        urls.push(SitemapUrl {
+                            url: l,
+                            lastmod: m.and_then(|m| parse_lastmod(&m)),
+                            priority: p
+                                .and_then(|p| p.trim().parse::<f32>().ok())
+                                .filter(|p| (0.0..=1.0).contains(p))
+                                .unwrap_or(Self::DEFAULT_PRIORITY),
+                            changefreq: c.and_then(|c| ChangeFreq::parse(&c)),
+                        });
+                    }
+                }
+                Event::Text(e) => {
+                    let text = e.unescape()?.to_string();
+                    set_field(
+                        &path,
+                        text,
+                        &mut loc,
+                        &mut lastmod,
+                        &mut priority,
+                        &mut changefreq,
+                    );
+                }
+                Event::CData(e) => {
+                    let text = String::from_utf8_lossy(&e).to_string();
+                    set_field(
+                        &path,
+                        text,
+                        &mut loc,
+                        &mut lastmod,
+                        &mut priority,
+                        &mut changefreq,
+                    );
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        match is_index {
+            Some(true) => Ok(Sitemap::Index(sitemaps)),
+            Some(false) => Ok(Sitemap::UrlSet(urls)),
+            None => Err(anyhow!("empty sitemap")),
+        }
+    }
+
+    async fn fetch_one(client: &Client, url: &Url) -> Result<Self> {
+        let res = client
+            .get(url.clone())
+            .header(USER_AGENT, FOXEYE_USER_AGENT)
+            .send()
+            .await?;
+
+        if !res.status().is_success() {
+            return Err(anyhow!("sitemap {url} returned {}", res.status()));
+        }
+
+        let Some(body) = read_limited(res, Self::MAX_SIZE as usize).await? else {
+            return Err(anyhow!(
+                "sitemap {url} is larger than {} bytes",
+                Self::MAX_SIZE
+            ));
+        };
+
+        Sitemap::parse(&Self::decode(&body)?)
+    }
+
+    // gzipped sitemaps are detected by magic bytes, servers label them inconsistently
+    fn decode(body: &[u8]) -> Result<Vec<u8>> {
+        if !body.starts_with(&[0x1f, 0x8b]) {
+            return Ok(body.to_vec());
+        }
+
+        // the size limit applies to the uncompressed sitemap, a small gzip can inflate far beyond it
+        let mut decoded = vec![];
+        GzDecoder::new(body)
+            .take(Self::MAX_SIZE + 1)
+            .read_to_end(&mut decoded)?;
+        if decoded.len() as u64 > Self::MAX_SIZE {
+            return Err(anyhow!(
+                "sitemap is larger than {} bytes uncompressed",
+                Self::MAX_SIZE
+            ));
+        }
+
+        Ok(decoded)
+    }
+
+    // fetches a sitemap, following sitemap indexes, and returns every url found
+    pub async fn fetch(client: &Client, url: Url) -> Result<Vec<SitemapUrl>> {
+        let mut queue = vec![url];
+        let mut visited = HashSet::new();
+        let mut urls = vec![];
+
+        while let Some(url) = queue.pop() {
+            if visited.len() >= Self::MAX_SITEMAPS {
+                warn!("sitemap limit of {} reached, stopping", Self::MAX_SITEMAPS);
+                break;
+            }
+            if !visited.insert(url.clone()) {
+                continue;
+            }
+
+            match Self::fetch_one(client, &url).await {
+                Ok(Sitemap::UrlSet(u)) => {
+                    info!("sitemap {url}: found {} urls", u.len());
+                    urls.extend(u);
+                }
+                Ok(Sitemap::Index(s)) => {
+                    info!("sitemap index {url}: found {} sitemaps", s.len());
+                    queue.extend(s);
+                }
+                Err(e) => warn!("failed to fetch sitemap {url}: {e}"),
+            }
+        }
+
+        Ok(urls)
+    }
+}
+
+fn set_field(
+    path: &[String],
+    text: String,
+    loc: &mut Option<String>,
+    lastmod: &mut Option<String>,
+    priority: &mut Option<String>,
+    changefreq: &mut Option<String>,
+) {
+    // only direct children of <url> or <sitemap>, ignores extensions like <image:loc>
+    let [.., parent, field] = path else {
+        return;
+    };
+    if parent != "url" && parent != "sitemap" {
+        return;
+    }
+
+    let target = match field.as_str() {
+        "loc" => loc,
+        "lastmod" => lastmod,
+        "priority" => priority,
+        "changefreq" => changefreq,
+        _ => return,
+    };
+    target.get_or_insert_with(String::new).push_str(&text);
+}
+
+// W3C datetime, either a full timestamp or just a date
+fn parse_lastmod(s: &str) -> Option<PrimitiveDateTime> {
+    let s = s.trim();
+
+    if let Ok(dt) = OffsetDateTime::parse(s, &Rfc3339) {
+        let dt = dt.to_offset(UtcOffset::UTC);
+        return Some(PrimitiveDateTime::new(dt.date(), dt.time()));
+    }
+
+    Date::parse(s, format_description!("[year]-[month]-[day]"))
+        .ok()
+        .map(|d| PrimitiveDateTime::new(d, Time::MIDNIGHT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_parse_urlset() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9"
+                    xmlns:image="http://www.google.com/schemas/sitemap-image/1.1">
+                <url>
+                    <loc>https://example.com/a?x=1&amp;y=2</loc>
+                    <lastmod>2024-06-01T10:00:00+02:00</lastmod>
+                    <changefreq>Weekly</changefreq>
+                    <priority>0.8</priority>
+                    <image:image><image:loc>https://example.com/img.png</image:loc></image:image>
+                </url>
+                <url>
+                    <loc><![CDATA[https://example.com/b]]></loc>
+                    <lastmod>2024-06-02</lastmod>
+                    <priority>7</priority>
+                </url>
+                <url><loc>not a url</loc></url>
+                <url><lastmod>2024-06-02</lastmod></url>
+                <url><loc>https://example.com/c</loc><lastmod>yesterday</lastmod></url>
+            </urlset>"#;
+
+        let Sitemap::UrlSet(urls) = Sitemap::parse(xml.as_bytes()).unwrap() else {
+            panic!("expected urlset");
+        };
+
+        assert_eq!(
+            urls,
+            vec![
+                SitemapUrl {
+                    url: Url::parse("https://example.com/a?x=1&y=2").unwrap(),
+                    lastmod: Some(datetime!(2024-06-01 08:00:00)),
+                    priority: 0.8,
+                    changefreq: Some(ChangeFreq::Weekly),
+                },
+                SitemapUrl {
+                    url: Url::parse("https://example.com/b").unwrap(),
+                    lastmod: Some(datetime!(2024-06-02 00:00:00)),
+                    priority: 0.5,
+                    changefreq: None,
+                },
+                SitemapUrl {
+                    url: Url::parse("https://example.com/c").unwrap(),
+                    lastmod: None,
+                    priority: 0.5,
+                    changefreq: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_modified_since() {
+        let url = |lastmod| SitemapUrl {
+            url: Url::parse("https://example.com/").unwrap(),
+            lastmod,
+            priority: 0.5,
+            changefreq: None,
+        };
+        let crawled = Some(datetime!(2024-06-01 12:00:00));
+
+        assert!(url(Some(datetime!(2024-06-02 00:00:00))).modified_since(crawled));
+        assert!(!url(Some(datetime!(2024-06-01 12:00:00))).modified_since(crawled));
+        assert!(!url(Some(datetime!(2024-05-01 00:00:00))).modified_since(crawled));
+        // no lastmod, or never crawled, has to be fetched to find out
+        assert!(url(None).modified_since(crawled));
+        assert!(url(Some(datetime!(2024-05-01 00:00:00))).modified_since(None));
+    }
+
+    #[test]
+    fn test_parse_index() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                <sitemap>
+                    <loc>https://example.com/sitemap1.xml.gz</loc>
+                    <lastmod>2024-06-01</lastmod>
+                </sitemap>
+                <sitemap><loc>https://example.com/sitemap2.xml</loc></sitemap>
+            </sitemapindex>"#;
+
+        assert_eq!(
+            Sitemap::parse(xml.as_bytes()).unwrap(),
+            Sitemap::Index(vec![
+                Url::parse("https://example.com/sitemap1.xml.gz").unwrap(),
+                Url::parse("https://example.com/sitemap2.xml").unwrap(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(Sitemap::parse(b"").is_err());
+        assert!(Sitemap::parse(b"<html><body>not found</body></html>").is_err());
+    }
+
+    #[test]
+    fn test_decode() {
+        let xml = "<urlset><url><loc>https://example.com/</loc></url></urlset>";
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(xml.as_bytes()).unwrap();
+        let gz = encoder.finish().unwrap();
+
+        assert_eq!(Sitemap::decode(&gz).unwrap(), xml.as_bytes());
+        assert_eq!(Sitemap::decode(xml.as_bytes()).unwrap(), xml.as_bytes());
+
+        // a gzip bomb is rejected instead of being cut off at the limit
+        let mut encoder = GzEncoder::new(vec![], Compression::best());
+        let zeros = vec![0; 1024 * 1024];
+        for _ in 0..=Sitemap::MAX_SIZE / zeros.len() as u64 {
+            encoder.write_all(&zeros).unwrap();
+        }
+        let bomb = encoder.finish().unwrap();
+        assert!(bomb.len() < 1024 * 1024);
+        assert!(Sitemap::decode(&bomb).is_err());
+    }
+}
@@ -0,0 +1,725 @@
+use std::env;
+use std::fs::{create_dir_all, rename, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_ENCODING, TRANSFER_ENCODING};
+use reqwest::{Request, Response, StatusCode, Version};
+use sha2::{Digest, Sha256};
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::OffsetDateTime;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::spawn_blocking;
+use tracing::{error, info};
+use ulid::Ulid;
+use url::Url;
+
+use crate::config::FOXEYE_USER_AGENT;
+
+// request line and headers as sent, executing the request consumes it
+#[derive(Debug, Clone)]
+pub struct RequestHead {
+    url: Url,
+    method: String,
+    version: Version,
+    headers: HeaderMap,
+}
+
+impl RequestHead {
+    pub fn new(req: &Request) -> Self {
+        RequestHead {
+            url: req.url().clone(),
+            method: req.method().to_string(),
+            version: req.version(),
+            headers: req.headers().clone(),
+        }
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+}
+
+// status line and headers as received, the body is read separately
+#[derive(Debug, Clone)]
+pub struct ResponseHead {
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap,
+    remote_addr: Option<SocketAddr>,
+}
+
+impl ResponseHead {
+    pub fn new(res: &Response) -> Self {
+        ResponseHead {
+            status: res.status(),
+            version: res.version(),
+            headers: res.headers().clone(),
+            remote_addr: res.remote_addr(),
+        }
+    }
+}
+
+enum Archived {
+    Exchange(Box<(RequestHead, ResponseHead, Option<Vec<u8>>)>),
+    Close(oneshot::Sender<Result<()>>),
+}
+
+// hands fetched responses to a WarcWriter on a blocking thread of its own
+// compression and file writes never hold up the workers, records are written in the order they are sent
+#[derive(Debug, Clone)]
+pub struct WarcArchive {
+    tx: mpsc::Sender<Archived>,
+}
+
+impl WarcArchive {
+    // records waiting to be written, workers wait for room when the disk can't keep up
+    const BUFFER: usize = 64;
+
+    pub fn from_env(worker_id: &str) -> Result<Option<WarcArchive>> {
+        Ok(WarcWriter::from_env(worker_id)?.map(WarcArchive::spawn))
+    }
+
+    fn spawn(mut writer: WarcWriter) -> WarcArchive {
+        let (tx, mut rx) = mpsc::channel(Self::BUFFER);
+        spawn_blocking(move || {
+            while let Some(archived) = rx.blocking_recv() {
+                match archived {
+                    Archived::Exchange(exchange) => {
+                        let (request, response, body) = *exchange;
+                        if let Err(e) = writer.write(&request, &response, body.as_deref()) {
+                            error!("archive: error while writing {} to WARC {e}", request.url());
+                        }
+                    }
+                    Archived::Close(done) => {
+                        let _ = done.send(writer.close());
+                    }
+                }
+            }
+
+            // every sender is gone, the crawler stopped without closing the file
+            if let Err(e) = writer.close() {
+                error!("archive: error while closing WARC file {e}");
+            }
+        });
+
+        WarcArchive { tx }
+    }
+
+    pub async fn write(&self, request: RequestHead, response: ResponseHead, body: Option<Vec<u8>>) {
+        let url = request.url().clone();
+        let archived = Archived::Exchange(Box::new((request, response, body)));
+        if self.tx.send(archived).await.is_err() {
+            error!("archive: WARC writer stopped, {url} isn't archived");
+        }
+    }
+
+    // finishes the current file once the records sent before are written
+    pub async fn close(&self) -> Result<()> {
+        let (done, closed) = oneshot::channel();
+        self.tx
+            .send(Archived::Close(done))
+            .await
+            .map_err(|_| anyhow!("WARC writer stopped"))?;
+        closed.await?
+    }
+}
+
+// archives fetched responses with their requests in gzip WARC 1.1 files, one gzip member per record
+// a file is written as <name>.open and renamed once it reaches the max size or the crawler stops
+#[derive(Debug)]
+pub struct WarcWriter {
+    dir: PathBuf,
+    prefix: String,
+    max_size: u64,
+    serial: u32,
+    current: Option<(PathBuf, File, u64)>, // final path, file and bytes written so far
+}
+
+impl WarcWriter {
+    const DEFAULT_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+    // WARC_DIR turns archiving on, WARC_MAX_SIZE is the size in bytes files are rotated at
+    pub fn from_env(worker_id: &str) -> Result<Option<WarcWriter>> {
+        let Ok(dir) = env::var("WARC_DIR") else {
+            return Ok(None);
+        };
+        let max_size = match env::var("WARC_MAX_SIZE") {
+            Ok(size) => size.parse()?,
+            Err(_) => Self::DEFAULT_MAX_SIZE,
+        };
+
+        let dir = PathBuf::from(dir);
+        create_dir_all(&dir)?;
+        info!("archiving responses to {} in WARC files", dir.display());
+
+        Ok(Some(WarcWriter {
+            dir,
+            prefix: format!("foxeye-{worker_id}"),
+            max_size,
+            serial: 0,
+            current: None,
+        }))
+    }
+
+    // a response record and the request record it answers, body is none when it wasn't downloaded
+    pub fn write(
+        &mut self,
+        request: &RequestHead,
+        response: &ResponseHead,
+        body: Option<&[u8]>,
+    ) -> Result<()> {
+        let date = OffsetDateTime::now_utc().format(&Rfc3339)?;
+        let response_id = record_id();
+
+        let mut block = http_head(
+            &format!("{:?} {}", response.version, response.status),
+            &response.headers,
+        );
+        block.extend_from_slice(body.unwrap_or_default());
+
+        let mut fields = vec![
+            ("WARC-Target-URI", request.url.to_string()),
+            ("WARC-Date", date.clone()),
+        ];
+        if let Some(addr) = response.remote_addr {
+            fields.push(("WARC-IP-Address", addr.ip().to_string()));
+        }
+        if body.is_none() {
+            fields.push(("WARC-Truncated", "unspecified".to_string()));
+        }
+        let response_record = record(
+            "response",
+            &response_id,
+            &fields,
+            "application/http;msgtype=response",
+            &block,
+        );
+        self.append(&response_record)?;
+
+        let mut path = request.url.path().to_string();
+        if let Some(query) = request.url.query() {
+            path = format!("{path}?{query}");
+        }
+        let mut headers = request.headers.clone();
+        if let Ok(host) = request.url.authority().parse() {
+            headers.insert(reqwest::header::HOST, host);
+        }
+        let block = http_head(
+            &format!("{} {path} {:?}", request.method, request.version),
+            &headers,
+        );
+        let fields = [
+            ("WARC-Target-URI", request.url.to_string()),
+            ("WARC-Date", date),
+            ("WARC-Concurrent-To", response_id),
+        ];
+        let request_record = record(
+            "request",
+            &record_id(),
+            &fields,
+            "application/http;msgtype=request",
+            &block,
+        );
+        self.append(&request_record)?;
+
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|(.., size)| *size >= self.max_size)
+        {
+            self.close()?;
+        }
+
+        Ok(())
+    }
+
+    // finishes the current file, the next record starts a new one
+    pub fn close(&mut self) -> Result<()> {
+        if let Some((path, mut file, size)) = self.current.take() {
+            file.flush()?;
+            rename(open_path(&path), &path)?;
+            info!("closed WARC file {} of {size} bytes", path.display());
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, record: &[u8]) -> Result<()> {
+        if self.current.is_none() {
+            self.open()?;
+        }
+        let Some((_, file, size)) = self.current.as_mut() else {
+            return Ok(());
+        };
+
+        let member = gzip(record)?;
+        file.write_all(&member)?;
+        *size += member.len() as u64;
+
+        Ok(())
+    }
+
+    // <prefix>-<timestamp>-<serial>.warc.gz starting with a warcinfo record
+    fn open(&mut self) -> Result<()> {
+        let now = OffsetDateTime::now_utc();
+        let timestamp = now.format(format_description!(
+            "[year][month][day][hour][minute][second]"
+        ))?;
+        self.serial += 1;
+        let name = format!("{}-{timestamp}-{:05}.warc.gz", self.prefix, self.serial);
+        let path = self.dir.join(&name);
+
+        let mut file = File::create(open_path(&path))?;
+        let info = format!(
+            "software: foxeye crawler\r\nformat: WARC File Format 1.1\r\n\
+            conformsTo: https://iipc.github.io/warc-specifications/specifications/warc-format/warc-1.1/\r\n\
+            http-header-user-agent: {FOXEYE_USER_AGENT}\r\n"
+        );
+        let fields = [
+            ("WARC-Date", now.format(&Rfc3339)?),
+            ("WARC-Filename", name),
+        ];
+        let member = gzip(&record(
+            "warcinfo",
+            &record_id(),
+            &fields,
+            "application/warc-fields",
+            info.as_bytes(),
+        ))?;
+        file.write_all(&member)?;
+
+        info!("opened WARC file {}", path.display());
+        self.current = Some((path, file, member.len() as u64));
+        Ok(())
+    }
+}
+
+// a record read back from a WARC file
+#[derive(Debug)]
+pub struct WarcRecord {
+    headers: Vec<(String, String)>,
+    pub block: Vec<u8>,
+}
+
+impl WarcRecord {
+    // header names are case-insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    // WARC 1.0 writers wrap the uri in angle brackets
+    pub fn target_uri(&self) -> Option<&str> {
+        self.header("WARC-Target-URI")
+            .map(|u| u.trim_start_matches('<').trim_end_matches('>'))
+    }
+
+    // a complete http response, records cut short while archiving are skipped
+    pub fn is_response(&self) -> bool {
+        self.header("WARC-Type") == Some("response")
+            && self.header("WARC-Truncated").is_none()
+            && self
+                .header("Content-Type")
+                .is_some_and(|t| t.starts_with("application/http"))
+    }
+}
+
+// reads records of .warc and .warc.gz files, ours or those of other crawlers
+pub struct WarcReader<R> {
+    reader: R,
+}
+
+impl WarcReader<Box<dyn BufRead + Send>> {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        let reader: Box<dyn BufRead + Send> = match path.extension() {
+            Some(ext) if ext == "gz" => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+            _ => Box::new(BufReader::new(file)),
+        };
+        Ok(WarcReader { reader })
+    }
+}
+
+impl<R: BufRead> WarcReader<R> {
+    // none at the end of the file
+    pub fn next_record(&mut self) -> Result<Option<WarcRecord>> {
+        // records are separated by blank lines
+        let mut line = vec![];
+        loop {
+            line.clear();
+            if self.reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(None);
+            }
+            if !line.trim_ascii().is_empty() {
+                break;
+            }
+        }
+        if !line.starts_with(b"WARC/") {
+            bail!(
+                "expected a WARC record, found {}",
+                String::from_utf8_lossy(line.trim_ascii())
+            );
+        }
+
+        let mut headers: Vec<(String, String)> = vec![];
+        loop {
+            line.clear();
+            if self.reader.read_until(b'\n', &mut line)? == 0 {
+                bail!("WARC record ends within its headers");
+            }
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end_matches(['\r', '\n']);
+            if text.is_empty() {
+                break;
+            }
+            // folded header lines continue the previous value
+            if text.starts_with([' ', '\t']) {
+                if let Some((_, value)) = headers.last_mut() {
+                    value.push(' ');
+                    value.push_str(text.trim());
+                }
+                continue;
+            }
+            if let Some((name, value)) = text.split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+
+        let length = headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case("Content-Length"))
+            .and_then(|(_, v)| v.parse::<u64>().ok())
+            .ok_or(anyhow!("WARC record without content length"))?;
+
+        let mut block = vec![];
+        (&mut self.reader).take(length).read_to_end(&mut block)?;
+        if (block.len() as u64) < length {
+            bail!("WARC record ends within its block");
+        }
+
+        Ok(Some(WarcRecord { headers, block }))
+    }
+}
+
+// status, headers and body of the http response in a response record's block
+pub fn parse_response(block: &[u8]) -> Result<(StatusCode, HeaderMap, &[u8])> {
+    let (head, body) = match block.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(end) => (&block[..end], &block[end + 4..]),
+        None => match block.windows(2).position(|w| w == b"\n\n") {
+            Some(end) => (&block[..end], &block[end + 2..]),
+            None => (block, &[][..]),
+        },
+    };
+
+    let head = String::from_utf8_lossy(head);
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|s| s.parse::<StatusCode>().ok())
+        .ok_or(anyhow!("response record without a status line"))?;
+
+    let mut headers = HeaderMap::new();
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.trim().as_bytes()),
+            HeaderValue::from_str(value.trim()),
+        ) {
+            headers.append(name, value);
+        }
+    }
+
+    Ok((status, headers, body))
+}
+
+// the body as served, archived payloads keep their transfer and content encoding
+pub fn decode_payload(headers: &HeaderMap, body: &[u8]) -> Result<Vec<u8>> {
+    let has = |name, value| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|v| v.trim().eq_ignore_ascii_case(value))
+    };
+
+    // our own records keep the header but hold the body as reqwest put it back together
+    let mut payload = match has(TRANSFER_ENCODING, "chunked") {
+        true => dechunk(body).unwrap_or_else(|| body.to_vec()),
+        false => body.to_vec(),
+    };
+
+    if has(CONTENT_ENCODING, "gzip") || has(CONTENT_ENCODING, "x-gzip") {
+        let mut decoded = vec![];
+        MultiGzDecoder::new(payload.as_slice()).read_to_end(&mut decoded)?;
+        payload = decoded;
+    } else if has(CONTENT_ENCODING, "deflate") {
+        let mut decoded = vec![];
+        ZlibDecoder::new(payload.as_slice()).read_to_end(&mut decoded)?;
+        payload = decoded;
+    } else if let Some(encoding) = headers
+        .get(CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.trim().eq_ignore_ascii_case("identity"))
+    {
+        bail!("unsupported content encoding {encoding}");
+    }
+
+    Ok(payload)
+}
+
+// none if the body isn't chunked after all
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut payload = vec![];
+    loop {
+        let end = body.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..end]).ok()?;
+        // chunk extensions follow the size
+        let size = size.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        body = &body[end + 2..];
+        if size == 0 {
+            return Some(payload);
+        }
+        payload.extend_from_slice(body.get(..size)?);
+        body = body.get(size..)?.strip_prefix(b"\r\n")?;
+    }
+}
+
+fn open_path(path: &Path) -> PathBuf {
+    let mut open = path.as_os_str().to_owned();
+    open.push(".open");
+    PathBuf::from(open)
+}
+
+fn record_id() -> String {
+    let id = format!("{:032x}", Ulid::new().0);
+    format!(
+        "<urn:uuid:{}-{}-{}-{}-{}>",
+        &id[..8],
+        &id[8..12],
+        &id[12..16],
+        &id[16..20],
+        &id[20..]
+    )
+}
+
+fn record(
+    kind: &str,
+    id: &str,
+    fields: &[(&str, String)],
+    content_type: &str,
+    block: &[u8],
+) -> Vec<u8> {
+    let mut header = format!("WARC/1.1\r\nWARC-Type: {kind}\r\nWARC-Record-ID: {id}\r\n");
+    for (name, value) in fields {
+        header.push_str(&format!("{name}: {value}\r\n"));
+    }
+    header.push_str(&format!(
+        "WARC-Block-Digest: sha256:{:x}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\r\n",
+        Sha256::digest(block),
+        block.len()
+    ));
+
+    let mut record = header.into_bytes();
+    record.extend_from_slice(block);
+    record.extend_from_slice(b"\r\n\r\n");
+    record
+}
+
+fn http_head(start_line: &str, headers: &HeaderMap) -> Vec<u8> {
+    let mut head = format!("{start_line}\r\n").into_bytes();
+    for (name, value) in headers {
+        head.extend_from_slice(name.as_str().as_bytes());
+        head.extend_from_slice(b": ");
+        head.extend_from_slice(value.as_bytes());
+        head.extend_from_slice(b"\r\n");
+    }
+    head.extend_from_slice(b"\r\n");
+    head
+}
+
+fn gzip(record: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(record)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::MultiGzDecoder;
+
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let dir = env::temp_dir().join(format!("foxeye-warc-{}", Ulid::new()));
+        let mut warc = WarcWriter {
+            dir: dir.clone(),
+            prefix: "foxeye-test".to_string(),
+            max_size: 1,
+            serial: 0,
+            current: None,
+        };
+
+        let client = reqwest::Client::new();
+        let req = client
+            .get("https://x/a?b=1")
+            .header("user-agent", FOXEYE_USER_AGENT)
+            .build()
+            .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "text/html".parse().unwrap());
+        let res = ResponseHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers,
+            remote_addr: None,
+        };
+
+        create_dir_all(&dir).unwrap();
+        warc.write(&RequestHead::new(&req), &res, Some(b"<html>hi</html>"))
+            .unwrap();
+
+        // rotated right away with a max size of 1 byte
+        assert!(warc.current.is_none());
+        let files = std::fs::read_dir(&dir).unwrap().collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        let path = files[0].as_ref().unwrap().path();
+        assert!(path.to_str().unwrap().ends_with("-00001.warc.gz"));
+
+        let mut text = String::new();
+        MultiGzDecoder::new(File::open(&path).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let types = text
+            .lines()
+            .filter_map(|l| l.strip_prefix("WARC-Type: "))
+            .collect::<Vec<_>>();
+        assert_eq!(types, ["warcinfo", "response", "request"]);
+        assert!(text
+            .contains("HTTP/1.1 200 OK\r\ncontent-type: text/html\r\n\r\n<html>hi</html>\r\n\r\n"));
+        assert!(text.contains("GET /a?b=1 HTTP/1.1\r\n"));
+        assert!(text.contains("host: x\r\n"));
+        assert!(text.contains("Content-Length: 59\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_archive() {
+        let dir = env::temp_dir().join(format!("foxeye-warc-{}", Ulid::new()));
+        create_dir_all(&dir).unwrap();
+        let archive = WarcArchive::spawn(WarcWriter {
+            dir: dir.clone(),
+            prefix: "foxeye-test".to_string(),
+            max_size: WarcWriter::DEFAULT_MAX_SIZE,
+            serial: 0,
+            current: None,
+        });
+
+        let req = reqwest::Client::new().get("https://x/a").build().unwrap();
+        let res = ResponseHead {
+            status: StatusCode::NOT_FOUND,
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            remote_addr: None,
+        };
+        for _ in 0..3 {
+            archive
+                .write(RequestHead::new(&req), res.clone(), None)
+                .await;
+        }
+        archive.close().await.unwrap();
+
+        // the file is only renamed to its final name once every record before the close is in it
+        let files = std::fs::read_dir(&dir).unwrap().collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        let path = files[0].as_ref().unwrap().path();
+        assert!(path.to_str().unwrap().ends_with("-00001.warc.gz"));
+        let mut text = String::new();
+        MultiGzDecoder::new(File::open(&path).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(text.matches("HTTP/1.1 404 Not Found\r\n").count(), 3);
+    }
+
+    #[test]
+    fn test_read() {
+        let chunked =
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nTransfer-Encoding: chunked\r\n\r\n\
+            6\r\n<html>\r\n7\r\n</html>\r\n0\r\n\r\n";
+        let mut gzipped = GzEncoder::new(vec![], Compression::default());
+        gzipped.write_all(b"hello").unwrap();
+        let mut encoded = b"HTTP/1.0 200 OK\nContent-Encoding: gzip\n\n".to_vec();
+        encoded.extend(gzipped.finish().unwrap());
+
+        // 1.0 style target uri and a folded header
+        let mut file = b"WARC/1.0\r\nWARC-Type: response\r\nWARC-Target-URI: <https://x/a>\r\n\
+            Content-Type: application/http;\r\n msgtype=response\r\n"
+            .to_vec();
+        file.extend(format!("Content-Length: {}\r\n\r\n", chunked.len()).as_bytes());
+        file.extend(chunked);
+        file.extend(b"\r\n\r\n");
+        file.extend(record(
+            "response",
+            &record_id(),
+            &[("WARC-Target-URI", "https://x/b".to_string())],
+            "application/http;msgtype=response",
+            &encoded,
+        ));
+        file.extend(record(
+            "request",
+            &record_id(),
+            &[],
+            "application/http;msgtype=request",
+            b"GET /b HTTP/1.1\r\n\r\n",
+        ));
+
+        let mut warc = WarcReader {
+            reader: file.as_slice(),
+        };
+        let mut records = vec![];
+        while let Some(record) = warc.next_record().unwrap() {
+            records.push(record);
+        }
+        assert_eq!(records.len(), 3);
+        assert!(!records[2].is_response());
+        assert_eq!(
+            records[0].header("content-type"),
+            Some("application/http; msgtype=response")
+        );
+
+        let cases = [("https://x/a", "<html></html>"), ("https://x/b", "hello")];
+        for (record, (uri, payload)) in records.iter().zip(cases) {
+            assert!(record.is_response());
+            assert_eq!(record.target_uri(), Some(uri));
+            let (status, headers, body) = parse_response(&record.block).unwrap();
+            assert_eq!(status, StatusCode::OK);
+            let decoded = decode_payload(&headers, body).unwrap();
+            assert_eq!(String::from_utf8(decoded).unwrap(), payload, "{uri}");
+        }
+
+        // a body reqwest already put back together is left alone
+        let headers = parse_response(chunked).unwrap().1;
+        assert_eq!(
+            decode_payload(&headers, b"<html></html>").unwrap(),
+            b"<html></html>"
+        );
+    }
+}
@@ -1,8 +1,12 @@
 pub mod db;
+pub mod sites;
+pub mod traps;
 
 use thiserror::Error;
 
 pub use db::Db;
+pub use sites::{SiteChanges, SiteRow};
+pub use traps::TrapRow;
 
 #[derive(Debug, Error)]
 pub enum DbError {
@@ -0,0 +1,270 @@
+use sqlx::{Acquire, FromRow};
+
+use crate::{Db, DbError};
+
+// a row of the sites table, the crawler and the parser both read their site config from it
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct SiteRow {
+    pub host: String,
+    pub url: String, // seed url
+    pub depth: Option<i32>,
+    pub rps: Option<i32>, // requests per second
+    pub max_body_size: Option<i64>,
+    pub paused: bool,
+    pub path_prefix: Option<String>, // none for the directory of the seed url
+    pub include_patterns: Vec<String>,
+    pub exclude_patterns: Vec<String>,
+    pub domains: Vec<String>, // other hosts of the site, e.g. *.rust-lang.org
+    pub external_hops: i32,   // links followed off the site's hosts
+    pub weight: f32,          // scales the crawl priority of the site's urls
+    pub max_pages: Option<i32>, // per budget cycle, none for no limit
+    pub max_bytes: Option<i64>,
+    pub max_crawl_secs: Option<i32>,
+    pub budget_cycle_secs: i32,
+}
+
+// fields of a site to change, none leaves the field as is
+// Some(None) clears a field that can be unset, e.g. removes the site's depth limit
+#[derive(Debug, Clone, Default)]
+pub struct SiteChanges {
+    pub depth: Option<Option<i32>>,
+    pub rps: Option<Option<i32>>,
+    pub max_body_size: Option<Option<i64>>,
+    pub path_prefix: Option<Option<String>>,
+    pub include_patterns: Option<Vec<String>>,
+    pub exclude_patterns: Option<Vec<String>>,
+    pub domains: Option<Vec<String>>,
+    pub external_hops: Option<i32>,
+    pub weight: Option<f32>,
+    pub max_pages: Option<Option<i32>>,
+    pub max_bytes: Option<Option<i64>>,
+    pub max_crawl_secs: Option<Option<i32>>,
+    pub budget_cycle_secs: Option<i32>,
+}
+
+impl Db {
+    pub async fn get_sites(&self) -> Result<Vec<SiteRow>, DbError> {
+        let mut pool = self.get_pg().await?;
+
+        let sites = sqlx::query_as::<_, SiteRow>(
+            "SELECT host, url, depth, rps, max_body_size, paused, path_prefix, include_patterns, exclude_patterns, domains, external_hops, weight, max_pages, max_bytes, max_crawl_secs, budget_cycle_secs FROM sites ORDER BY host",
+        )
+        .fetch_all(pool.acquire().await?)
+        .await?;
+
+        Ok(sites)
+    }
+
+    pub async fn get_site(&self, host: &str) -> Result<Option<SiteRow>, DbError> {
+        let mut pool = self.get_pg().await?;
+
+        let site = sqlx::query_as::<_, SiteRow>(
+            "SELECT host, url, depth, rps, max_body_size, paused, path_prefix, include_patterns, exclude_patterns, domains, external_hops, weight, max_pages, max_bytes, max_crawl_secs, budget_cycle_secs FROM sites WHERE host = $1",
+        )
+        .bind(host)
+        .fetch_optional(pool.acquire().await?)
+        .await?;
+
+        Ok(site)
+    }
+
+    // returns false if the host already exists
+    pub async fn insert_site(&self, site: &SiteRow) -> Result<bool, DbError> {
+        let mut pool = self.get_pg().await?;
+
+        let res = sqlx::query(
+            r#"
+            INSERT INTO sites (host, url, depth, rps, max_body_size, paused,
+                path_prefix, include_patterns, exclude_patterns, domains, external_hops, weight,
+                max_pages, max_bytes, max_crawl_secs, budget_cycle_secs)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+            ON CONFLICT (host) DO NOTHING
+            "#,
+        )
+        .bind(&site.host)
+        .bind(&site.url)
+        .bind(site.depth)
+        .bind(site.rps)
+        .bind(site.max_body_size)
+        .bind(site.paused)
+        .bind(&site.path_prefix)
+        .bind(&site.include_patterns)
+        .bind(&site.exclude_patterns)
+        .bind(&site.domains)
+        .bind(site.external_hops)
+        .bind(site.weight)
+        .bind(site.max_pages)
+        .bind(site.max_bytes)
+        .bind(site.max_crawl_secs)
+        .bind(site.budget_cycle_secs)
+        .execute(pool.acquire().await?)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    // only the given fields change, none if the host doesn't exist
+    // COALESCE can't tell a cleared field from an unchanged one, nullable fields come with a flag
+    pub async fn update_site(
+        &self,
+        host: &str,
+        changes: &SiteChanges,
+    ) -> Result<Option<SiteRow>, DbError> {
+        let mut pool = self.get_pg().await?;
+
+        let site = sqlx::query_as::<_, SiteRow>(
+            r#"
+            UPDATE sites SET
+                depth = CASE WHEN $2 THEN $3 ELSE depth END,
+                rps = CASE WHEN $4 THEN $5 ELSE rps END,
+                max_body_size = CASE WHEN $6 THEN $7 ELSE max_body_size END,
+                path_prefix = CASE WHEN $8 THEN $9 ELSE path_prefix END,
+                include_patterns = COALESCE($10, include_patterns),
+                exclude_patterns = COALESCE($11, exclude_patterns),
+                domains = COALESCE($12, domains),
+                external_hops = COALESCE($13, external_hops),
+                weight = COALESCE($14, weight),
+                max_pages = CASE WHEN $15 THEN $16 ELSE max_pages END,
+                max_bytes = CASE WHEN $17 THEN $18 ELSE max_bytes END,
+                max_crawl_secs = CASE WHEN $19 THEN $20 ELSE max_crawl_secs END,
+                budget_cycle_secs = COALESCE($21, budget_cycle_secs)
+            WHERE host = $1
+            RETURNING host, url, depth, rps, max_body_size, paused, path_prefix, include_patterns, exclude_patterns, domains, external_hops, weight,
+                max_pages, max_bytes, max_crawl_secs, budget_cycle_secs
+            "#,
+        )
+        .bind(host)
+        .bind(changes.depth.is_some())
+        .bind(changes.depth.flatten())
+        .bind(changes.rps.is_some())
+        .bind(changes.rps.flatten())
+        .bind(changes.max_body_size.is_some())
+        .bind(changes.max_body_size.flatten())
+        .bind(changes.path_prefix.is_some())
+        .bind(changes.path_prefix.clone().flatten())
+        .bind(&changes.include_patterns)
+        .bind(&changes.exclude_patterns)
+        .bind(&changes.domains)
+        .bind(changes.external_hops)
+        .bind(changes.weight)
+        .bind(changes.max_pages.is_some())
+        .bind(changes.max_pages.flatten())
+        .bind(changes.max_bytes.is_some())
+        .bind(changes.max_bytes.flatten())
+        .bind(changes.max_crawl_secs.is_some())
+        .bind(changes.max_crawl_secs.flatten())
+        .bind(changes.budget_cycle_secs)
+        .fetch_optional(pool.acquire().await?)
+        .await?;
+
+        Ok(site)
+    }
+
+    pub async fn set_site_paused(
+        &self,
+        host: &str,
+        paused: bool,
+    ) -> Result<Option<SiteRow>, DbError> {
+        let mut pool = self.get_pg().await?;
+
+        let site = sqlx::query_as::<_, SiteRow>(
+            r#"
+            UPDATE sites SET paused = $2
+            WHERE host = $1
+            RETURNING host, url, depth, rps, max_body_size, paused, path_prefix, include_patterns, exclude_patterns, domains, external_hops, weight,
+                max_pages, max_bytes, max_crawl_secs, budget_cycle_secs
+            "#,
+        )
+        .bind(host)
+        .bind(paused)
+        .fetch_optional(pool.acquire().await?)
+        .await?;
+
+        Ok(site)
+    }
+
+    // returns false if the host doesn't exist
+    pub async fn delete_site(&self, host: &str) -> Result<bool, DbError> {
+        let mut pool = self.get_pg().await?;
+
+        let res = sqlx::query("DELETE FROM sites WHERE host = $1")
+            .bind(host)
+            .execute(pool.acquire().await?)
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    // inserts new sites, existing ones are only updated if `update` is set, paused is left alone
+    // returns the hosts that were added or changed
+    pub async fn import_sites(
+        &self,
+        sites: &[SiteRow],
+        update: bool,
+    ) -> Result<Vec<String>, DbError> {
+        let mut pool = self.get_pg().await?;
+        let mut tx = pool.begin().await?;
+        let mut changed = vec![];
+
+        for site in sites {
+            let host = sqlx::query_as::<_, (String,)>(
+                r#"
+                INSERT INTO sites (host, url, depth, rps, max_body_size,
+                    path_prefix, include_patterns, exclude_patterns, domains, external_hops, weight,
+                    max_pages, max_bytes, max_crawl_secs, budget_cycle_secs)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+                ON CONFLICT (host) DO UPDATE SET
+                    url = EXCLUDED.url,
+                    depth = EXCLUDED.depth,
+                    rps = EXCLUDED.rps,
+                    max_body_size = EXCLUDED.max_body_size,
+                    path_prefix = EXCLUDED.path_prefix,
+                    include_patterns = EXCLUDED.include_patterns,
+                    exclude_patterns = EXCLUDED.exclude_patterns,
+                    domains = EXCLUDED.domains,
+                    external_hops = EXCLUDED.external_hops,
+                    weight = EXCLUDED.weight,
+                    max_pages = EXCLUDED.max_pages,
+                    max_bytes = EXCLUDED.max_bytes,
+                    max_crawl_secs = EXCLUDED.max_crawl_secs,
+                    budget_cycle_secs = EXCLUDED.budget_cycle_secs
+                WHERE $16 AND (sites.url, sites.depth, sites.rps, sites.max_body_size,
+                    sites.path_prefix, sites.include_patterns, sites.exclude_patterns,
+                    sites.domains, sites.external_hops, sites.weight,
+                    sites.max_pages, sites.max_bytes, sites.max_crawl_secs, sites.budget_cycle_secs)
+                    IS DISTINCT FROM (EXCLUDED.url, EXCLUDED.depth, EXCLUDED.rps, EXCLUDED.max_body_size,
+                    EXCLUDED.path_prefix, EXCLUDED.include_patterns, EXCLUDED.exclude_patterns,
+                    EXCLUDED.domains, EXCLUDED.external_hops, EXCLUDED.weight,
+                    EXCLUDED.max_pages, EXCLUDED.max_bytes, EXCLUDED.max_crawl_secs, EXCLUDED.budget_cycle_secs)
+                RETURNING host
+                "#,
+            )
+            .bind(&site.host)
+            .bind(&site.url)
+            .bind(site.depth)
+            .bind(site.rps)
+            .bind(site.max_body_size)
+            .bind(&site.path_prefix)
+            .bind(&site.include_patterns)
+            .bind(&site.exclude_patterns)
+            .bind(&site.domains)
+            .bind(site.external_hops)
+            .bind(site.weight)
+            .bind(site.max_pages)
+            .bind(site.max_bytes)
+            .bind(site.max_crawl_secs)
+            .bind(site.budget_cycle_secs)
+            .bind(update)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            if let Some((host,)) = host {
+                changed.push(host);
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(changed)
+    }
+}
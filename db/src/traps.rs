@@ -0,0 +1,59 @@
+use sqlx::{Acquire, FromRow};
+
+use crate::{Db, DbError};
+
+// urls of a host rejected as crawl traps for one reason
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct TrapRow {
+    pub host: String,
+    pub reason: String,
+    pub rejected: i64,
+}
+
+impl Db {
+    // adds to the counts of crawl_traps, a (host, reason) pair may only appear once
+    pub async fn count_traps(&self, traps: &[(String, String, i64)]) -> Result<(), DbError> {
+        if traps.is_empty() {
+            return Ok(());
+        }
+        let mut pool = self.get_pg().await?;
+
+        let mut hosts = vec![];
+        let mut reasons = vec![];
+        let mut rejected = vec![];
+        for (host, reason, n) in traps {
+            hosts.push(host.as_str());
+            reasons.push(reason.as_str());
+            rejected.push(*n);
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO crawl_traps (host, reason, rejected)
+                SELECT * FROM UNNEST($1::text[], $2::text[], $3::bigint[])
+                ON CONFLICT (host, reason) DO UPDATE SET
+                    rejected = crawl_traps.rejected + EXCLUDED.rejected
+            "#,
+        )
+        .bind(&hosts)
+        .bind(&reasons)
+        .bind(&rejected)
+        .execute(pool.acquire().await?)
+        .await?;
+
+        Ok(())
+    }
+
+    // hosts trapping us the most first
+    pub async fn get_traps(&self) -> Result<Vec<TrapRow>, DbError> {
+        let mut pool = self.get_pg().await?;
+
+        let traps = sqlx::query_as::<_, TrapRow>(
+            "SELECT host, reason, rejected FROM crawl_traps ORDER BY rejected DESC, host, reason",
+        )
+        .fetch_all(pool.acquire().await?)
+        .await?;
+
+        Ok(traps)
+    }
+}
@@ -37,7 +37,7 @@ impl Embedder {
         Ok(Self {
             db,
             embed,
-            auto_ack: true,
+            auto_ack: false,
         })
     }
 
@@ -78,6 +78,12 @@ impl Embedder {
         let doc_ids = vec![doc_id.to_string(); embedding.len()];
 
         let mut pool = self.db.get_pg().await?;
+        let mut tx = pool.begin().await?;
+
+        // re-crawled documents keep their doc_id, replace the chunks of the previous version
+        sqlx::query!("DELETE FROM chunk WHERE doc_id = $1", doc_id)
+            .execute(&mut *tx)
+            .await?;
 
         let res = sqlx::query!(
             r#"
@@ -96,9 +102,11 @@ impl Embedder {
             &chunk_ends,
             embedding as Vec<Vector>
         )
-        .execute(pool.acquire().await?)
+        .execute(&mut *tx)
         .await?;
 
+        tx.commit().await?;
+
         info!(
             "wrote {} chunks to db, rows affected {}",
             chunk_ids.len(),
@@ -131,13 +139,6 @@ impl AsyncConsumer for Embedder {
         _basic_properties: BasicProperties,
         content: Vec<u8>,
     ) {
-        // ack explicitly if manual ack
-        if !self.auto_ack {
-            info!("ack to delivery {} on channel {}", deliver, channel);
-            let args = BasicAckArguments::new(deliver.delivery_tag(), false);
-            channel.basic_ack(args).await.unwrap();
-        }
-
         let id = String::from_utf8(content).unwrap();
         info!("received id from parser, embedding now {id}");
         let now = Instant::now();
@@ -145,5 +146,14 @@ impl AsyncConsumer for Embedder {
             error!("amq consumer::embedder error while embedding id {id}: {e}");
         }
         info!("embedded {id} in {}", now.elapsed().as_secs_f32());
+
+        // ack once the message is done with, a shutdown or crash before this redelivers it
+        if !self.auto_ack {
+            info!("ack to delivery {} on channel {}", deliver, channel);
+            let args = BasicAckArguments::new(deliver.delivery_tag(), false);
+            if let Err(e) = channel.basic_ack(args).await {
+                error!("amq consumer::embedder error while acking {id}: {e}");
+            }
+        }
     }
 }
@@ -1,8 +1,7 @@
 use crate::embedder::Embedder;
 use anyhow::{anyhow, Result};
 use std::env;
-use tokio::sync::Notify;
-use utils::RabbitMQ;
+use utils::{shutdown_signal, RabbitMQ};
 
 mod embed;
 mod embedder;
@@ -23,12 +22,15 @@ async fn main() -> Result<()> {
     .await?;
 
     let embedder = Embedder::new().await?;
-    let guard = Notify::new();
 
-    amq.consume(&amq.consumer_tag, embedder.auto_ack, embedder)
-        .await?;
-
-    guard.notified().await;
+    // on SIGTERM / SIGINT stop taking messages and finish the ones in flight
+    amq.consume_until(
+        &amq.consumer_tag,
+        embedder.auto_ack,
+        embedder,
+        shutdown_signal(),
+    )
+    .await?;
 
     Ok(())
 }
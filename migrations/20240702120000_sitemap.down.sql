@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE crawler_queue DROP COLUMN IF EXISTS changefreq;
+ALTER TABLE crawler_queue DROP COLUMN IF EXISTS lastmod;
+ALTER TABLE crawler_queue DROP COLUMN IF EXISTS sitemap_priority;
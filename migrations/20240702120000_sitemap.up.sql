@@ -0,0 +1,4 @@
+-- Add up migration script here
+ALTER TABLE crawler_queue ADD COLUMN IF NOT EXISTS sitemap_priority REAL;
+ALTER TABLE crawler_queue ADD COLUMN IF NOT EXISTS lastmod TIMESTAMP;
+ALTER TABLE crawler_queue ADD COLUMN IF NOT EXISTS changefreq TEXT;
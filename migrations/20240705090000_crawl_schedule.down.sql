@@ -0,0 +1,6 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_host_next_fetch_at;
+
+ALTER TABLE crawler_queue DROP COLUMN IF EXISTS last_error;
+ALTER TABLE crawler_queue DROP COLUMN IF EXISTS attempts;
+ALTER TABLE crawler_queue DROP COLUMN IF EXISTS next_fetch_at;
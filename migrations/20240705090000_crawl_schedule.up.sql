@@ -0,0 +1,6 @@
+-- Add up migration script here
+ALTER TABLE crawler_queue ADD COLUMN IF NOT EXISTS next_fetch_at TIMESTAMP NOT NULL DEFAULT now();
+ALTER TABLE crawler_queue ADD COLUMN IF NOT EXISTS attempts INT NOT NULL DEFAULT 0;
+ALTER TABLE crawler_queue ADD COLUMN IF NOT EXISTS last_error TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_host_next_fetch_at ON crawler_queue(host, next_fetch_at);
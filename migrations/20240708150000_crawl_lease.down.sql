@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_lease_expires_at;
+
+ALTER TABLE crawler_queue DROP COLUMN IF EXISTS lease_expires_at;
+ALTER TABLE crawler_queue DROP COLUMN IF EXISTS leased_by;
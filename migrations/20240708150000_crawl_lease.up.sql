@@ -0,0 +1,5 @@
+-- Add up migration script here
+ALTER TABLE crawler_queue ADD COLUMN IF NOT EXISTS leased_by TEXT;
+ALTER TABLE crawler_queue ADD COLUMN IF NOT EXISTS lease_expires_at TIMESTAMP;
+
+CREATE INDEX IF NOT EXISTS idx_lease_expires_at ON crawler_queue(lease_expires_at);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TRIGGER IF EXISTS set_timestamp_crawl_state ON crawl_state;
+DROP TABLE IF EXISTS crawl_state;
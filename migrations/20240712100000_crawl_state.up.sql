@@ -0,0 +1,17 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS crawl_state (
+    url TEXT PRIMARY KEY ,
+    etag TEXT,
+    last_modified TEXT,
+    content_hash TEXT,
+    refresh_interval INT NOT NULL , -- seconds between re-crawls, adapts to how often the page changes
+    last_crawled_at TIMESTAMP,
+    last_changed_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT now(),
+    updated_at TIMESTAMP DEFAULT now()
+);
+
+CREATE TRIGGER set_timestamp_crawl_state
+    BEFORE UPDATE ON crawl_state
+    FOR EACH ROW
+EXECUTE PROCEDURE update_updated_at_column();
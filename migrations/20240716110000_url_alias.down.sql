@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TRIGGER IF EXISTS set_timestamp_url_alias ON url_alias;
+DROP TABLE IF EXISTS url_alias;
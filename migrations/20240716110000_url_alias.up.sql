@@ -0,0 +1,14 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS url_alias (
+    alias TEXT PRIMARY KEY , -- canonical url that redirects
+    url TEXT NOT NULL , -- canonical url it ends up at
+    created_at TIMESTAMP DEFAULT now(),
+    updated_at TIMESTAMP DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_alias_url ON url_alias(url);
+
+CREATE TRIGGER set_timestamp_url_alias
+    BEFORE UPDATE ON url_alias
+    FOR EACH ROW
+EXECUTE PROCEDURE update_updated_at_column();
@@ -0,0 +1,5 @@
+-- Add down migration script here
+ALTER TABLE document DROP COLUMN IF EXISTS gone_at;
+
+DROP TRIGGER IF EXISTS set_timestamp_crawl_errors ON crawl_errors;
+DROP TABLE IF EXISTS crawl_errors;
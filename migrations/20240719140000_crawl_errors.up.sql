@@ -0,0 +1,21 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS crawl_errors (
+    url TEXT PRIMARY KEY ,
+    host TEXT NOT NULL ,
+    depth INT NOT NULL ,
+    status INT , -- http status, null for timeouts and network errors
+    error TEXT ,
+    attempts INT NOT NULL DEFAULT 0 ,
+    created_at TIMESTAMP DEFAULT now(),
+    updated_at TIMESTAMP DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_crawl_errors_host ON crawl_errors(host);
+
+CREATE TRIGGER set_timestamp_crawl_errors
+    BEFORE UPDATE ON crawl_errors
+    FOR EACH ROW
+EXECUTE PROCEDURE update_updated_at_column();
+
+-- documents whose page answers 404 / 410 are kept as tombstones
+ALTER TABLE document ADD COLUMN IF NOT EXISTS gone_at TIMESTAMP;
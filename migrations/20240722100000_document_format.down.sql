@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE document DROP COLUMN IF EXISTS format;
@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE document ADD COLUMN IF NOT EXISTS format TEXT NOT NULL DEFAULT 'html'; -- html, pdf, text or markdown
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TRIGGER IF EXISTS set_timestamp_sites ON sites;
+DROP TABLE IF EXISTS sites;
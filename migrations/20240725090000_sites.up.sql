@@ -0,0 +1,16 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS sites (
+    host TEXT PRIMARY KEY ,
+    url TEXT NOT NULL , -- seed url
+    depth INT ,
+    rps INT , -- requests per second
+    max_body_size BIGINT , -- bytes
+    paused BOOLEAN NOT NULL DEFAULT false ,
+    created_at TIMESTAMP DEFAULT now(),
+    updated_at TIMESTAMP DEFAULT now()
+);
+
+CREATE TRIGGER set_timestamp_sites
+    BEFORE UPDATE ON sites
+    FOR EACH ROW
+EXECUTE PROCEDURE update_updated_at_column();
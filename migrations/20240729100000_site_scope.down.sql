@@ -0,0 +1,5 @@
+-- Add down migration script here
+ALTER TABLE sites
+    DROP COLUMN IF EXISTS path_prefix,
+    DROP COLUMN IF EXISTS include_patterns,
+    DROP COLUMN IF EXISTS exclude_patterns;
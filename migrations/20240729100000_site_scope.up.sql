@@ -0,0 +1,5 @@
+-- Add up migration script here
+ALTER TABLE sites
+    ADD COLUMN path_prefix TEXT , -- defaults to the directory of the seed url
+    ADD COLUMN include_patterns TEXT[] NOT NULL DEFAULT '{}' ,
+    ADD COLUMN exclude_patterns TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,9 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_crawler_queue_site;
+
+ALTER TABLE crawler_queue DROP COLUMN IF EXISTS hops;
+ALTER TABLE crawler_queue DROP COLUMN IF EXISTS site;
+
+ALTER TABLE sites
+    DROP COLUMN IF EXISTS domains,
+    DROP COLUMN IF EXISTS external_hops;
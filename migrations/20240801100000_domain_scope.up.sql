@@ -0,0 +1,10 @@
+-- Add up migration script here
+ALTER TABLE sites
+    ADD COLUMN domains TEXT[] NOT NULL DEFAULT '{}' , -- e.g. *.rust-lang.org
+    ADD COLUMN external_hops INT NOT NULL DEFAULT 0; -- links followed off the site's domains
+
+-- configured site a url was discovered for, hosts other than the site's own are crawled on its behalf
+ALTER TABLE crawler_queue ADD COLUMN IF NOT EXISTS site TEXT;
+ALTER TABLE crawler_queue ADD COLUMN IF NOT EXISTS hops INT NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS idx_crawler_queue_site ON crawler_queue(site);
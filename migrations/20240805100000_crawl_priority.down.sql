@@ -0,0 +1,14 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_host_priority;
+
+DROP TRIGGER IF EXISTS rescore_crawler_queue ON sites;
+DROP FUNCTION IF EXISTS rescore_site_urls;
+
+DROP TRIGGER IF EXISTS set_priority_crawler_queue ON crawler_queue;
+DROP FUNCTION IF EXISTS set_crawl_priority;
+DROP FUNCTION IF EXISTS crawl_priority;
+
+ALTER TABLE crawler_queue DROP COLUMN IF EXISTS priority;
+ALTER TABLE crawler_queue DROP COLUMN IF EXISTS inlinks;
+
+ALTER TABLE sites DROP COLUMN IF EXISTS weight;
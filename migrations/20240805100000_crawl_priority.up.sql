@@ -0,0 +1,49 @@
+-- Add up migration script here
+ALTER TABLE sites ADD COLUMN IF NOT EXISTS weight REAL NOT NULL DEFAULT 1; -- scales the priority of the site's urls
+
+ALTER TABLE crawler_queue ADD COLUMN IF NOT EXISTS inlinks INT NOT NULL DEFAULT 0; -- links to the url seen by the parser
+ALTER TABLE crawler_queue ADD COLUMN IF NOT EXISTS priority REAL NOT NULL DEFAULT 0;
+
+-- shallow pages, pages many others link to and pages the sitemap ranks high come first
+CREATE OR REPLACE FUNCTION crawl_priority(depth INT, inlinks INT, sitemap_priority REAL, weight REAL)
+    RETURNS REAL AS $$
+    SELECT (weight * (0.5 + COALESCE(sitemap_priority, 0.5)) * ln(2 + inlinks) / (1 + depth))::REAL;
+$$ language 'sql' IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION set_crawl_priority()
+    RETURNS TRIGGER AS $$
+BEGIN
+    NEW.priority = crawl_priority(
+        NEW.depth,
+        NEW.inlinks,
+        NEW.sitemap_priority,
+        COALESCE((SELECT weight FROM sites WHERE host = NEW.site), 1)
+    );
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER set_priority_crawler_queue
+    BEFORE INSERT OR UPDATE OF depth, inlinks, sitemap_priority, site ON crawler_queue
+    FOR EACH ROW
+EXECUTE PROCEDURE set_crawl_priority();
+
+-- a site's new weight applies to the urls already queued for it
+CREATE OR REPLACE FUNCTION rescore_site_urls()
+    RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE crawler_queue SET site = site WHERE site = NEW.host;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER rescore_crawler_queue
+    AFTER UPDATE OF weight ON sites
+    FOR EACH ROW
+    WHEN (OLD.weight IS DISTINCT FROM NEW.weight)
+EXECUTE PROCEDURE rescore_site_urls();
+
+-- score the urls already queued
+UPDATE crawler_queue SET depth = depth;
+
+CREATE INDEX IF NOT EXISTS idx_host_priority ON crawler_queue(host, priority DESC);
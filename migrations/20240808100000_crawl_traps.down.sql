@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_crawler_queue_path;
+
+DROP TRIGGER IF EXISTS set_timestamp_crawl_traps ON crawl_traps;
+DROP TABLE IF EXISTS crawl_traps;
@@ -0,0 +1,18 @@
+-- Add up migration script here
+-- urls rejected as crawl traps, counted per host and reason
+CREATE TABLE IF NOT EXISTS crawl_traps (
+    host TEXT NOT NULL ,
+    reason TEXT NOT NULL ,
+    rejected BIGINT NOT NULL DEFAULT 0 ,
+    created_at TIMESTAMP DEFAULT now(),
+    updated_at TIMESTAMP DEFAULT now(),
+    PRIMARY KEY (host, reason)
+);
+
+CREATE TRIGGER set_timestamp_crawl_traps
+    BEFORE UPDATE ON crawl_traps
+    FOR EACH ROW
+EXECUTE PROCEDURE update_updated_at_column();
+
+-- query string variants of a path are counted before queueing
+CREATE INDEX IF NOT EXISTS idx_crawler_queue_path ON crawler_queue (split_part(url, '?', 1));
@@ -0,0 +1,8 @@
+-- Add down migration script here
+DROP TRIGGER IF EXISTS set_timestamp_crawl_budgets ON crawl_budgets;
+DROP TABLE IF EXISTS crawl_budgets;
+
+ALTER TABLE sites DROP COLUMN IF EXISTS budget_cycle_secs;
+ALTER TABLE sites DROP COLUMN IF EXISTS max_crawl_secs;
+ALTER TABLE sites DROP COLUMN IF EXISTS max_bytes;
+ALTER TABLE sites DROP COLUMN IF EXISTS max_pages;
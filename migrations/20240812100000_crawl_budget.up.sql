@@ -0,0 +1,22 @@
+-- Add up migration script here
+-- what a site may crawl per budget cycle, null for no limit
+ALTER TABLE sites ADD COLUMN IF NOT EXISTS max_pages INT;
+ALTER TABLE sites ADD COLUMN IF NOT EXISTS max_bytes BIGINT;
+ALTER TABLE sites ADD COLUMN IF NOT EXISTS max_crawl_secs INT;
+ALTER TABLE sites ADD COLUMN IF NOT EXISTS budget_cycle_secs INT NOT NULL DEFAULT 86400;
+
+-- what a site crawled in its current cycle, across its hosts and crawler instances
+CREATE TABLE IF NOT EXISTS crawl_budgets (
+    site TEXT PRIMARY KEY ,
+    cycle_started_at TIMESTAMP NOT NULL DEFAULT now(),
+    pages BIGINT NOT NULL DEFAULT 0 ,
+    bytes BIGINT NOT NULL DEFAULT 0 ,
+    crawl_ms BIGINT NOT NULL DEFAULT 0 ,
+    created_at TIMESTAMP DEFAULT now(),
+    updated_at TIMESTAMP DEFAULT now()
+);
+
+CREATE TRIGGER set_timestamp_crawl_budgets
+    BEFORE UPDATE ON crawl_budgets
+    FOR EACH ROW
+EXECUTE PROCEDURE update_updated_at_column();
@@ -0,0 +1,7 @@
+-- Add down migration script here
+DROP TRIGGER IF EXISTS set_timestamp_near_duplicate ON near_duplicate;
+DROP TABLE IF EXISTS near_duplicate;
+
+DROP INDEX IF EXISTS idx_document_host;
+ALTER TABLE document DROP COLUMN IF EXISTS simhash;
+ALTER TABLE document DROP COLUMN IF EXISTS host;
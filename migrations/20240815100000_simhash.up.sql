@@ -0,0 +1,24 @@
+-- Add up migration script here
+-- simhash of the document text, near-duplicates are looked up among documents of the same host
+ALTER TABLE document ADD COLUMN IF NOT EXISTS host TEXT;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS simhash BIGINT;
+
+UPDATE document SET host = substring(url FROM '^[a-z]+://([^/:?#]+)') WHERE host IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_document_host ON document(host) WHERE simhash IS NOT NULL;
+
+-- pages whose text is almost the same as a document's, they are not saved or embedded themselves
+CREATE TABLE IF NOT EXISTS near_duplicate (
+    url TEXT PRIMARY KEY , -- canonical url of the page
+    doc_id VARCHAR(128) NOT NULL REFERENCES document(doc_id) ON DELETE CASCADE , -- document it duplicates
+    distance INT NOT NULL , -- hamming distance of the simhashes
+    created_at TIMESTAMP DEFAULT now(),
+    updated_at TIMESTAMP DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_near_duplicate_doc ON near_duplicate(doc_id);
+
+CREATE TRIGGER set_timestamp_near_duplicate
+    BEFORE UPDATE ON near_duplicate
+    FOR EACH ROW
+EXECUTE PROCEDURE update_updated_at_column();
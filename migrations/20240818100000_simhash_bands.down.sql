@@ -0,0 +1,10 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_document_simhash_band0;
+DROP INDEX IF EXISTS idx_document_simhash_band1;
+DROP INDEX IF EXISTS idx_document_simhash_band2;
+DROP INDEX IF EXISTS idx_document_simhash_band3;
+
+ALTER TABLE document DROP COLUMN IF EXISTS simhash_band0;
+ALTER TABLE document DROP COLUMN IF EXISTS simhash_band1;
+ALTER TABLE document DROP COLUMN IF EXISTS simhash_band2;
+ALTER TABLE document DROP COLUMN IF EXISTS simhash_band3;
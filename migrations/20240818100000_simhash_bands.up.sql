@@ -0,0 +1,19 @@
+-- Add up migration script here
+-- the simhash split into 4 bands of 16 bits, fingerprints at most 3 bits apart share at least one band
+-- candidates are looked up by band through the indexes, only they are compared bit by bit
+ALTER TABLE document ADD COLUMN IF NOT EXISTS simhash_band0 INT;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS simhash_band1 INT;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS simhash_band2 INT;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS simhash_band3 INT;
+
+UPDATE document SET
+    simhash_band0 = (simhash & 65535)::int,
+    simhash_band1 = ((simhash >> 16) & 65535)::int,
+    simhash_band2 = ((simhash >> 32) & 65535)::int,
+    simhash_band3 = ((simhash >> 48) & 65535)::int
+WHERE simhash IS NOT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_document_simhash_band0 ON document(host, simhash_band0) WHERE simhash IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_document_simhash_band1 ON document(host, simhash_band1) WHERE simhash IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_document_simhash_band2 ON document(host, simhash_band2) WHERE simhash IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_document_simhash_band3 ON document(host, simhash_band3) WHERE simhash IS NOT NULL;
@@ -0,0 +1,5 @@
+-- Add down migration script here
+CREATE INDEX IF NOT EXISTS idx_crawler_queue_path ON crawler_queue (split_part(url, '?', 1));
+
+DROP INDEX IF EXISTS idx_query_variants_path;
+DROP TABLE IF EXISTS query_variants;
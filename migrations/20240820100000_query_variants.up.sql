@@ -0,0 +1,17 @@
+-- Add up migration script here
+-- query string variants of a path that were queued, kept after the crawler removes them from crawler_queue
+-- so a path can't be queued again with another MAX_QUERY_VARIANTS query strings once they were crawled
+CREATE TABLE IF NOT EXISTS query_variants (
+    url TEXT PRIMARY KEY ,
+    path TEXT NOT NULL ,
+    created_at TIMESTAMP DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_query_variants_path ON query_variants (path);
+
+INSERT INTO query_variants (url, path)
+    SELECT url, split_part(url, '?', 1) FROM crawler_queue WHERE url LIKE '%?%'
+    ON CONFLICT (url) DO NOTHING;
+
+-- variants are no longer counted on the queue
+DROP INDEX IF EXISTS idx_crawler_queue_path;
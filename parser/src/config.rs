@@ -1,48 +1,126 @@
-use anyhow::{anyhow, Result};
-use serde::{Deserialize, Serialize};
+use anyhow::Result;
+use db::{Db, SiteRow};
 use std::collections::HashMap;
-use std::fs::read_to_string;
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc::unbounded_channel;
+use tracing::{error, info};
 use url::Url;
+use utils::{matches_domain, ControlMessage, RabbitMQ, Scope};
 
-#[derive(Debug, Serialize, Deserialize)]
+// the crawler owns the sites table, the parser keeps a copy in sync through control messages
+#[derive(Debug)]
 struct Site {
-    url: String,
-    depth: u32,
-    rps: u32,
+    scope: Scope,
+    domains: Vec<String>,
+    external_hops: u32,
+}
+
+impl Site {
+    fn new(row: &SiteRow) -> Result<Self> {
+        let url = Url::parse(&row.url)?;
+        let scope = Scope::new(
+            &url,
+            row.path_prefix.as_deref(),
+            &row.include_patterns,
+            &row.exclude_patterns,
+        )?;
+
+        Ok(Site {
+            scope,
+            domains: row.domains.clone(),
+            external_hops: row.external_hops.max(0) as u32,
+        })
+    }
 }
 
 #[derive(Debug)]
 pub struct SiteConfig {
-    map: HashMap<String, Site>,
+    map: HashMap<String, Site>, // host of the site -> site
 }
 
 impl SiteConfig {
-    pub fn load_config() -> Result<Self> {
-        let sites = read_to_string("sites.json")?;
-        let val = serde_json::from_str::<serde_json::Value>(&sites)?;
-        let val = val
-            .get("sites")
-            .ok_or(anyhow!("no field \"sites\" found in config"))?;
-        let val = serde_json::from_value::<Vec<Site>>(val.to_owned())?;
-
+    pub async fn load_config(db: &Db) -> Result<Self> {
         let mut map = HashMap::new();
 
-        for site in val {
-            let url = Url::parse(&site.url)?;
-            let host = url.host().unwrap().clone().to_string();
-            map.insert(host, site);
+        for site in db.get_sites().await? {
+            map.insert(site.host.clone(), Site::new(&site)?);
         }
 
         Ok(SiteConfig { map })
     }
 
-    pub fn is_allowed(&self, host: String, _current_depth: u32) -> bool {
+    pub fn is_allowed(&self, url: &Url, _current_depth: u32) -> bool {
         // if let Some(site) = self.map.get(&host) {
         //     if current_depth <= site.depth {
         //         return true;
         //     }
         // }
         // false
-        self.map.contains_key(&host)
+        // links to paused sites are still queued, the crawler picks them up on resume
+        self.site_for(url).is_some()
+    }
+
+    // host of the site the url belongs to, through the site's own host or one of its domains
+    pub fn site_for(&self, url: &Url) -> Option<&str> {
+        let host = url.host_str()?;
+
+        if let Some((host, site)) = self.map.get_key_value(host) {
+            if site.scope.allows(url) {
+                return Some(host);
+            }
+        }
+
+        self.map
+            .iter()
+            .find(|(h, site)| {
+                h.as_str() != host
+                    && site.domains.iter().any(|d| matches_domain(host, d))
+                    && site.scope.for_other_host().allows(url)
+            })
+            .map(|(h, _)| h.as_str())
+    }
+
+    // links followed off the site's hosts
+    pub fn external_hops(&self, site: &str) -> u32 {
+        self.map.get(site).map_or(0, |s| s.external_hops)
+    }
+
+    // applies site changes announced by the crawler's admin api
+    pub async fn watch(config: Arc<RwLock<SiteConfig>>, db: Db, control: RabbitMQ) {
+        let (tx, mut rx) = unbounded_channel();
+        if let Err(e) = control.basic_consume(&control.consumer_tag, true, tx).await {
+            error!("watch: error while consuming control messages {e}");
+            return;
+        }
+
+        while let Some(message) = rx.recv().await {
+            let host = match serde_json::from_str::<ControlMessage>(&message) {
+                Ok(ControlMessage::SiteChanged { host }) => host,
+                Err(e) => {
+                    error!("watch: invalid control message {message} {e}");
+                    continue;
+                }
+            };
+
+            let site = match db.get_site(&host).await {
+                Ok(site) => site,
+                Err(e) => {
+                    error!("watch: error while reloading site {host} {e}");
+                    continue;
+                }
+            };
+
+            match site.map(|s| Site::new(&s)).transpose() {
+                Ok(Some(site)) => {
+                    info!("watch: reloaded site {host}");
+                    config.write().unwrap().map.insert(host, site);
+                }
+                Ok(None) => {
+                    info!("watch: removed site {host}");
+                    config.write().unwrap().map.remove(&host);
+                }
+                Err(e) => error!("watch: invalid config for site {host} {e}"),
+            }
+        }
     }
 }
@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Error, Result};
+use regex::Regex;
+use scraper::{Html, Selector};
+use url::Url;
+
+use crate::formats::{parse_markdown, parse_pdf, parse_text, DocumentFormat};
+use crate::robots::{is_nofollow, RobotsDirectives};
+use utils::Canonicalizer;
+
+// links, canonical link, title, text and <meta name="robots"> of an html page
+type ParsedDocument = (Vec<Url>, Option<Url>, String, String, RobotsDirectives);
+
+// what the parser gets out of a crawled document, before anything is saved or queued
+#[derive(Debug)]
+pub struct Extracted {
+    pub links: Vec<Url>,
+    pub canonical: Option<Url>, // html only
+    pub title: String,
+    pub text: String,
+    pub robots: RobotsDirectives, // from the document, X-Robots-Tag headers are merged by the caller
+}
+
+pub fn extract(
+    content: &str,
+    format: DocumentFormat,
+    url: &Url,
+    canonicalizer: &Canonicalizer,
+) -> Result<Extracted> {
+    let (links, canonical, title, text, robots) = match format {
+        DocumentFormat::Html => parse_html(content, url.clone(), canonicalizer)?,
+        _ => {
+            let (links, title, text) = parse_other(format, content, url, canonicalizer)?;
+            (links, None, title, text, RobotsDirectives::default())
+        }
+    };
+
+    Ok(Extracted {
+        links,
+        canonical,
+        title,
+        text,
+        robots,
+    })
+}
+
+// returns canonical links found on the page, its <link rel=canonical> if any, title, text
+// and the page's <meta name="robots"> directives, rel=nofollow links are left out
+fn parse_html(doc: &str, host: Url, canonicalizer: &Canonicalizer) -> Result<ParsedDocument> {
+    let mut document = Html::parse_document(doc);
+
+    let script_selector = Selector::parse("script").unwrap();
+    let style_selector = Selector::parse("style").unwrap();
+    let body = Selector::parse("body").unwrap();
+    let title = Selector::parse("title").unwrap();
+    let href = Selector::parse("a").unwrap();
+    let base = Selector::parse("base[href]").unwrap();
+    let link = Selector::parse("link[rel][href]").unwrap();
+    let meta = Selector::parse("meta[name][content]").unwrap();
+
+    let ids = document
+        .select(&script_selector)
+        .chain(document.select(&style_selector))
+        .map(|p| p.id())
+        .collect::<Vec<_>>();
+
+    for id in ids {
+        if let Some(node) = &mut document.tree.get_mut(id) {
+            node.detach();
+        }
+    }
+
+    let title = document.select(&title).next();
+    let body = document.select(&body).next();
+    let url_hrefs = document
+        .select(&href)
+        .filter(|e| !is_nofollow(e.value().attr("rel")))
+        .filter_map(|e| e.value().attr("href"))
+        .map(|m| m.to_string())
+        .collect::<Vec<_>>();
+
+    // relative links resolve against <base href> when the page sets one
+    let base = document
+        .select(&base)
+        .next()
+        .and_then(|e| e.value().attr("href"))
+        .and_then(|h| host.join(h.trim()).ok())
+        .unwrap_or(host);
+
+    let canonical = document
+        .select(&link)
+        .find(|e| {
+            e.value().attr("rel").is_some_and(|r| {
+                r.split_whitespace()
+                    .any(|r| r.eq_ignore_ascii_case("canonical"))
+            })
+        })
+        .and_then(|e| e.value().attr("href"))
+        .and_then(|h| canonicalizer.resolve(&base, h));
+
+    let robots = document
+        .select(&meta)
+        .filter_map(|e| Some((e.value().attr("name")?, e.value().attr("content")?)))
+        .map(|(name, content)| RobotsDirectives::from_meta(name, content))
+        .fold(RobotsDirectives::default(), RobotsDirectives::merge);
+
+    let title = if let Some(title) = title {
+        title.text().collect::<Vec<_>>().join(" ")
+    } else {
+        String::new()
+    };
+
+    let body = if let Some(body) = body {
+        body.text().collect::<Vec<_>>().join(" ")
+    } else {
+        String::new()
+    };
+
+    let mut seen = HashSet::new();
+    let urls = url_hrefs
+        .iter()
+        .filter_map(|h| canonicalizer.resolve(&base, h))
+        .filter(|u| seen.insert(u.clone()))
+        .collect::<Vec<_>>();
+
+    if body.is_empty() {
+        return Err(Error::msg("parse_html: body not found"));
+    }
+
+    let text = clean_text(&title, &body)?;
+
+    Ok((urls, canonical, title, text, robots))
+}
+
+// pdf, plain text and markdown, same output as parse_document without a canonical link
+fn parse_other(
+    format: DocumentFormat,
+    content: &str,
+    host: &Url,
+    canonicalizer: &Canonicalizer,
+) -> Result<(Vec<Url>, String, String)> {
+    let (urls, title, body) = match format {
+        DocumentFormat::Pdf => parse_pdf(content)?,
+        DocumentFormat::Text => parse_text(content, host, canonicalizer)?,
+        DocumentFormat::Markdown => parse_markdown(content, host, canonicalizer)?,
+        DocumentFormat::Html => return Err(anyhow!("parse_other: html goes to parse_html")),
+    };
+
+    let body = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    if body.is_empty() {
+        return Err(Error::msg("parse_other: no text found"));
+    }
+
+    let text = clean_text(&title, &body)?;
+
+    Ok((urls, title, text))
+}
+
+fn clean_text(title: &str, body: &str) -> Result<String> {
+    let text = format!("{title} {body}");
+    let reg = Regex::new(r"\[.*?]|[^\x00-\x7F]+| {4}|[\t\n\r]|<[^>]*>")?;
+    Ok(reg.replace_all(&text, "").to_string())
+}
@@ -0,0 +1,187 @@
+use anyhow::{anyhow, Result};
+use base64::prelude::*;
+use pulldown_cmark::{Event, Parser as MarkdownParser, Tag, TagEnd};
+use regex::Regex;
+use url::Url;
+
+use utils::Canonicalizer;
+
+// longest title taken from the first line of a document without one
+const MAX_TITLE_LEN: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DocumentFormat {
+    Html,
+    Pdf,
+    Text,
+    Markdown,
+}
+
+impl DocumentFormat {
+    // markdown is often served as text/plain, the extension tells it apart
+    pub fn from_content_type(content_type: &str, url: &Url) -> Option<Self> {
+        match content_type {
+            "text/html" | "application/xhtml+xml" => Some(DocumentFormat::Html),
+            "application/pdf" => Some(DocumentFormat::Pdf),
+            "text/markdown" | "text/x-markdown" => Some(DocumentFormat::Markdown),
+            "text/plain" => {
+                let path = url.path().to_lowercase();
+                if path.ends_with(".md") || path.ends_with(".markdown") {
+                    Some(DocumentFormat::Markdown)
+                } else {
+                    Some(DocumentFormat::Text)
+                }
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DocumentFormat::Html => "html",
+            DocumentFormat::Pdf => "pdf",
+            DocumentFormat::Text => "text",
+            DocumentFormat::Markdown => "markdown",
+        }
+    }
+}
+
+// content is base64 encoded pdf, see CrawlMessage
+pub fn parse_pdf(content: &str) -> Result<(Vec<Url>, String, String)> {
+    let bytes = BASE64_STANDARD.decode(content.trim())?;
+    let text = pdf_extract::extract_text_from_mem(&bytes)
+        .map_err(|e| anyhow!("parse_pdf: failed to extract text {e}"))?;
+
+    // pdfs don't link to much we can crawl, and their links aren't in the extracted text reliably
+    let title = first_line(&text);
+    Ok((vec![], title, text))
+}
+
+pub fn parse_text(
+    content: &str,
+    base: &Url,
+    canonicalizer: &Canonicalizer,
+) -> Result<(Vec<Url>, String, String)> {
+    let reg = Regex::new(r#"https?://[^\s<>"'()\[\]{}]+"#)?;
+    let urls = reg
+        .find_iter(content)
+        .map(|m| m.as_str().trim_end_matches(['.', ',', ';', ':', '!', '?']))
+        .filter_map(|u| canonicalizer.resolve(base, u))
+        .collect();
+
+    Ok((urls, first_line(content), content.to_string()))
+}
+
+pub fn parse_markdown(
+    content: &str,
+    base: &Url,
+    canonicalizer: &Canonicalizer,
+) -> Result<(Vec<Url>, String, String)> {
+    let mut urls = vec![];
+    let mut text = String::new();
+    let mut title = String::new();
+    let mut in_heading = false;
+    let mut title_done = false;
+
+    for event in MarkdownParser::new(content) {
+        match event {
+            Event::Start(Tag::Link { dest_url, .. }) => {
+                if let Some(u) = canonicalizer.resolve(base, &dest_url) {
+                    urls.push(u);
+                }
+            }
+            Event::Start(Tag::Heading { .. }) => in_heading = !title_done,
+            Event::End(TagEnd::Heading(_)) => {
+                title_done |= in_heading && !title.is_empty();
+                in_heading = false;
+                text.push('\n');
+            }
+            Event::Text(t) | Event::Code(t) => {
+                if in_heading {
+                    title.push_str(&t);
+                }
+                text.push_str(&t);
+            }
+            Event::SoftBreak | Event::HardBreak | Event::End(TagEnd::Paragraph) => text.push('\n'),
+            _ => {}
+        }
+    }
+
+    if title.is_empty() {
+        title = first_line(&text);
+    }
+
+    Ok((urls, title.trim().to_string(), text))
+}
+
+fn first_line(text: &str) -> String {
+    text.lines()
+        .map(|l| l.trim())
+        .find(|l| !l.is_empty())
+        .unwrap_or_default()
+        .chars()
+        .take(MAX_TITLE_LEN)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_content_type() {
+        let url = Url::parse("https://x/docs/readme.md").unwrap();
+        let page = Url::parse("https://x/docs/notes.txt").unwrap();
+
+        let cases = [
+            ("text/html", &page, Some(DocumentFormat::Html)),
+            ("application/xhtml+xml", &page, Some(DocumentFormat::Html)),
+            ("application/pdf", &page, Some(DocumentFormat::Pdf)),
+            ("text/markdown", &page, Some(DocumentFormat::Markdown)),
+            ("text/plain", &url, Some(DocumentFormat::Markdown)),
+            ("text/plain", &page, Some(DocumentFormat::Text)),
+            ("image/png", &page, None),
+        ];
+
+        for (content_type, url, expected) in cases {
+            assert_eq!(
+                DocumentFormat::from_content_type(content_type, url),
+                expected,
+                "{content_type} {url}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_text() {
+        let base = Url::parse("https://x/notes.txt").unwrap();
+        let content =
+            "\n  Release notes\nsee https://x/changelog. and (https://y/a?utm_source=z)\n";
+
+        let (urls, title, text) = parse_text(content, &base, &Canonicalizer::default()).unwrap();
+        assert_eq!(title, "Release notes");
+        assert_eq!(text, content);
+        assert_eq!(
+            urls.iter().map(Url::as_str).collect::<Vec<_>>(),
+            vec!["https://x/changelog", "https://y/a"]
+        );
+    }
+
+    #[test]
+    fn test_parse_markdown() {
+        let base = Url::parse("https://x/docs/readme.md").unwrap();
+        let content = "Intro line\n\n# The `foxeye` book\n\nSee [install](install.md) and \
+                       [home](https://x/#top).\n\n## Usage\n\nRun it.\n";
+
+        let (urls, title, text) =
+            parse_markdown(content, &base, &Canonicalizer::default()).unwrap();
+        assert_eq!(title, "The foxeye book");
+        assert_eq!(
+            urls.iter().map(Url::as_str).collect::<Vec<_>>(),
+            vec!["https://x/docs/install.md", "https://x/"]
+        );
+        assert!(text.contains("See install and home."));
+        assert!(text.contains("Usage"));
+        assert!(!text.contains("]("));
+    }
+}
@@ -0,0 +1,5 @@
+// extraction of links, title and text from crawled documents
+// shared by the parser binary and `crawler explain`, which must not save anything
+pub mod extract;
+pub mod formats;
+pub mod robots;
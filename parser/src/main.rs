@@ -1,12 +1,14 @@
 use anyhow::{anyhow, Result};
+use db::Db;
 use std::env;
-use tokio::sync::Notify;
-use utils::RabbitMQ;
+use utils::{shutdown_signal, RabbitMQ, CONTROL_EXCHANGE};
 
+use crate::config::SiteConfig;
 use crate::parser::Parser;
 
 mod config;
 mod parser;
+mod simhash;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -25,12 +27,24 @@ async fn main() -> Result<()> {
     .await?;
 
     let parser = Parser::new().await.unwrap();
-    // parser.send_missing_ids().await?; // don't call if embedder hasn't finished all embeddings
-    let guard = Notify::new();
 
-    amq.consume(&amq.consumer_tag, parser.auto_ack, parser)
-        .await?;
-    guard.notified().await;
+    let control =
+        RabbitMQ::broadcast(&amq_uri, "consumer.parser.control", CONTROL_EXCHANGE).await?;
+    tokio::spawn(SiteConfig::watch(
+        parser.site_config(),
+        Db::new(1).await?,
+        control,
+    ));
+
+    // parser.send_missing_ids().await?; // don't call if embedder hasn't finished all embeddings
+    // on SIGTERM / SIGINT stop taking messages and finish the ones in flight
+    amq.consume_until(
+        &amq.consumer_tag,
+        parser.auto_ack,
+        parser,
+        shutdown_signal(),
+    )
+    .await?;
 
     Ok(())
 }
@@ -1,8 +1,8 @@
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::sync::{Arc, RwLock};
 
 use anyhow::{anyhow, Error, Result};
-use regex::Regex;
-use scraper::{Html, Selector};
 use sqlx::Acquire;
 use tokio::time::Instant;
 use tracing::{error, info, warn};
@@ -10,16 +10,27 @@ use ulid::Ulid;
 use url::Url;
 
 use crate::config::SiteConfig;
+use crate::simhash::{bands, simhash, MAX_DISTANCE};
 use db::Db;
+use parser::extract::{extract, Extracted};
+use parser::formats::DocumentFormat;
+use parser::robots::RobotsDirectives;
 use utils::amqprs::channel::{BasicAckArguments, Channel};
 use utils::amqprs::{BasicProperties, Deliver};
 use utils::async_trait::async_trait;
-use utils::{amqprs::consumer::AsyncConsumer, CrawlMessage, RabbitMQ};
+use utils::traps::{query_variant_key, MAX_QUERY_VARIANTS};
+use utils::{
+    amqprs::consumer::AsyncConsumer, detect_trap, Canonicalizer, CrawlMessage, RabbitMQ, Trap,
+};
+
+// url, host, site and hops of a link headed for crawler_queue
+type QueuedLink = (Url, String, String, i32);
 
 pub struct Parser {
     db: Db,
     amq: RabbitMQ,
-    config: SiteConfig,
+    config: Arc<RwLock<SiteConfig>>, // kept up to date by SiteConfig::watch
+    canonicalizer: Canonicalizer,
     pub auto_ack: bool,
 }
 
@@ -37,121 +48,93 @@ impl Parser {
             "parser.embedder.exchange",
         )
         .await?;
-        let config = SiteConfig::load_config()?;
+        let config = Arc::new(RwLock::new(SiteConfig::load_config(&db).await?));
+        let canonicalizer = Canonicalizer::load_config()?;
 
         Ok(Self {
             db,
             amq,
             config,
-            auto_ack: true,
+            canonicalizer,
+            auto_ack: false,
         })
     }
 
+    pub fn site_config(&self) -> Arc<RwLock<SiteConfig>> {
+        self.config.clone()
+    }
+
+    fn is_allowed(&self, url: &Url, depth: u32) -> bool {
+        self.config.read().unwrap().is_allowed(url, depth)
+    }
+
     async fn get_document(&self, id: &str) -> Result<Option<Vec<u8>>> {
         let doc = self.db.get_cache(id).await?;
 
         Ok(doc)
     }
 
-    fn parse_document(&self, doc: String, host: Url) -> Result<(Vec<Url>, String, String)> {
-        let mut document = Html::parse_document(&doc);
-
-        let script_selector = Selector::parse("script").unwrap();
-        let style_selector = Selector::parse("style").unwrap();
-        let body = Selector::parse("body").unwrap();
-        let title = Selector::parse("title").unwrap();
-        let href = Selector::parse("a").unwrap();
-
-        let ids = document
-            .select(&script_selector)
-            .chain(document.select(&style_selector))
-            .map(|p| p.id())
+    // links are queued for the site they belong to, or for the site of the page while it has external hops left
+    // a url already queued gains an inlink, it counts towards its crawl priority
+    async fn save_urls(&self, urls: Vec<Url>, depth: i32, site: &str, hops: u32) -> Result<()> {
+        // a url can only be updated once per statement, /dir and /dir/ are queued once
+        let mut seen = HashSet::new();
+        let urls = urls
+            .into_iter()
+            .filter(|u| seen.insert(self.canonicalizer.canonicalize(u)))
             .collect::<Vec<_>>();
 
-        for id in ids {
-            if let Some(node) = &mut document.tree.get_mut(id) {
-                node.detach();
-            }
-        }
-
-        let title = document.select(&title).next();
-        let body = document.select(&body).next();
-        let url_hrefs = document
-            .select(&href)
-            .filter_map(|e| e.value().attr("href"))
-            .map(|m| m.to_string())
-            .collect::<Vec<_>>();
-
-        let title = if let Some(title) = title {
-            title.text().collect::<Vec<_>>().join(" ")
-        } else {
-            String::new()
-        };
+        let links = {
+            let config = self.config.read().unwrap();
+            let external_hops = config.external_hops(site);
 
-        let body = if let Some(body) = body {
-            body.text().collect::<Vec<_>>().join(" ")
-        } else {
-            String::new()
+            urls.iter()
+                .filter_map(|u| {
+                    // the crawler walks the directories of file:// sites, links between their files aren't queued
+                    if u.scheme() == "file" {
+                        return None;
+                    }
+                    let Some(host) = u.host_str() else {
+                        warn!("skipping url {u}, host not found in config");
+                        return None;
+                    };
+                    let (site, hops) = match config.site_for(u) {
+                        Some(s) => (s.to_string(), 0),
+                        None if hops < external_hops => (site.to_string(), hops + 1),
+                        None => return None,
+                    };
+                    Some((u.clone(), host.to_string(), site, hops as i32))
+                })
+                .collect::<Vec<_>>()
         };
+        let links = self.drop_traps(links).await?;
 
         let mut urls = vec![];
-
-        for url in url_hrefs {
-            if !url.starts_with("http") || url.starts_with('/') {
-                if url.starts_with('#') {
-                    warn!("url: {url} is a fragment, skipping");
-                    continue;
-                }
-                if let Ok(u) = host.join(&url) {
-                    urls.push(u);
-                    continue;
-                }
-            }
-
-            if let Ok(u) = Url::parse(&url) {
-                urls.push(u);
-            }
-        }
-
-        if body.is_empty() {
-            return Err(Error::msg("parse_document: body not found"));
+        let mut hosts = vec![];
+        let mut sites = vec![];
+        let mut hops = vec![];
+        for (url, host, site, hop) in links {
+            urls.push(url.to_string());
+            hosts.push(host);
+            sites.push(site);
+            hops.push(hop);
         }
 
-        let text = format!("{title} {body}");
-        let reg = Regex::new(r"\[.*?]|[^\x00-\x7F]+| {4}|[\t\n\r]|<[^>]*>")?;
-        let text = reg.replace_all(&text, "").to_string();
-
-        Ok((urls, title, text))
-    }
-
-    async fn save_urls(&self, urls: Vec<Url>, depth: i32) -> Result<()> {
-        let (urls, hosts): (Vec<_>, Vec<_>) = urls
-            .iter()
-            .filter_map(|u| {
-                if let Some(host) = u.host() {
-                    if self.config.is_allowed(host.to_string(), (depth + 1) as u32) {
-                        return Some((u.to_string(), host.to_string()));
-                    }
-                } else { 
-                    warn!("skipping url {u}, host not found in config");
-                }
-                None
-            })
-            .unzip();
-
         let depths = vec![depth + 1; urls.len()];
 
         let mut pool = self.db.get_pg().await?;
 
         let res = sqlx::query!(
             "
-            INSERT INTO crawler_queue (url, host, depth)
+            INSERT INTO crawler_queue (url, host, depth, site, hops)
                 SELECT * FROM 
-                UNNEST($1::text[], $2::text[], $3::int[])
-                ON CONFLICT DO NOTHING",
+                UNNEST($1::text[], $2::text[], $3::int[], $4::text[], $5::int[])
+                ON CONFLICT (url) DO UPDATE SET inlinks = crawler_queue.inlinks + 1",
             &urls[..],
             &hosts[..],
-            &depths[..]
+            &depths[..],
+            &sites[..],
+            &hops[..]
         )
         .execute(pool.acquire().await?)
         .await
@@ -166,31 +149,233 @@ impl Parser {
         Ok(())
     }
 
-    async fn save_document(&self, title: String, doc: String, url: Url) -> Result<String> {
+    // calendars, session ids and relative link loops would fill crawler_queue without end
+    // a path is queued with at most MAX_QUERY_VARIANTS query strings ever, rejected urls are counted per host
+    async fn drop_traps(&self, links: Vec<QueuedLink>) -> Result<Vec<QueuedLink>> {
+        let mut rejected = HashMap::new();
+        let mut links = links
+            .into_iter()
+            .filter(|(url, host, ..)| match detect_trap(url) {
+                Some(trap) => {
+                    *rejected.entry((host.clone(), trap)).or_insert(0) += 1;
+                    false
+                }
+                None => true,
+            })
+            .collect::<Vec<_>>();
+
+        let paths = links
+            .iter()
+            .filter(|(url, ..)| url.query().is_some())
+            .map(|(url, ..)| query_variant_key(url).to_string())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        if !paths.is_empty() {
+            let queued = links
+                .iter()
+                .map(|(u, ..)| u.to_string())
+                .collect::<Vec<_>>();
+            let mut pool = self.db.get_pg().await?;
+
+            // variants queued before, crawled or not, not counting the links of this page that were
+            let rows = sqlx::query!(
+                r#"
+                SELECT path, count(*) AS "variants!"
+                FROM query_variants
+                WHERE path = ANY($1) AND NOT url = ANY($2)
+                GROUP BY 1"#,
+                &paths[..],
+                &queued[..]
+            )
+            .fetch_all(pool.acquire().await?)
+            .await
+            .map_err(|e| Error::msg(format!("drop_traps: error while counting variants {e}")))?;
+
+            let mut variants = rows
+                .into_iter()
+                .map(|r| (r.path, r.variants as usize))
+                .collect::<HashMap<_, _>>();
+
+            links.retain(|(url, host, ..)| {
+                if url.query().is_none() {
+                    return true;
+                }
+                let count = variants
+                    .entry(query_variant_key(url).to_string())
+                    .or_insert(0);
+                if *count >= MAX_QUERY_VARIANTS {
+                    *rejected
+                        .entry((host.clone(), Trap::TooManyVariants))
+                        .or_insert(0) += 1;
+                    return false;
+                }
+                *count += 1;
+                true
+            });
+
+            let (variants, paths): (Vec<_>, Vec<_>) = links
+                .iter()
+                .filter(|(url, ..)| url.query().is_some())
+                .map(|(url, ..)| (url.to_string(), query_variant_key(url).to_string()))
+                .unzip();
+            sqlx::query!(
+                "
+                INSERT INTO query_variants (url, path)
+                    SELECT * FROM UNNEST($1::text[], $2::text[])
+                    ON CONFLICT (url) DO NOTHING",
+                &variants[..],
+                &paths[..]
+            )
+            .execute(pool.acquire().await?)
+            .await
+            .map_err(|e| Error::msg(format!("drop_traps: error while saving variants {e}")))?;
+        }
+
+        if !rejected.is_empty() {
+            info!("dropped {} crawl trap urls", rejected.values().sum::<i64>());
+            let traps = rejected
+                .into_iter()
+                .map(|((host, trap), n)| (host, trap.as_str().to_string(), n))
+                .collect::<Vec<_>>();
+            self.db.count_traps(&traps).await?;
+        }
+
+        Ok(links)
+    }
+
+    async fn save_document(
+        &self,
+        title: String,
+        doc: String,
+        url: Url,
+        format: DocumentFormat,
+        fingerprint: Option<u64>,
+    ) -> Result<String> {
         let mut pool = self.db.get_pg().await?;
+        let mut tx = pool.begin().await?;
         let id = Ulid::new().to_string();
+        let host = url.host_str().map(|h| h.to_string());
         let url = url.to_string();
+        let band = |i: usize| fingerprint.map(|f| bands(f)[i]);
 
         let rec = sqlx::query!(
             r#"
-            INSERT INTO document (doc_id, url, content, title)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO document (doc_id, url, content, title, format, host, simhash,
+                simhash_band0, simhash_band1, simhash_band2, simhash_band3)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
             ON CONFLICT (url)
-            DO UPDATE SET content=$2
+            DO UPDATE SET content=$3, title=$4, format=$5, host=$6, simhash=$7,
+                simhash_band0=$8, simhash_band1=$9, simhash_band2=$10, simhash_band3=$11, gone_at=NULL
             RETURNING doc_id 
             "#,
             id,
             url,
             doc,
-            title
+            title,
+            format.as_str(),
+            host,
+            fingerprint.map(|f| f as i64),
+            band(0),
+            band(1),
+            band(2),
+            band(3)
         )
-        .fetch_one(pool.acquire().await?)
+        .fetch_one(&mut *tx)
         .await?;
 
+        // the page changed enough to stand on its own
+        sqlx::query!("DELETE FROM near_duplicate WHERE url = $1", url)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
         info!("save_document: saved document {id} to db");
         Ok(rec.doc_id)
     }
 
+    // the closest fingerprint among the other documents of the url's host, with its distance
+    // only documents sharing a band can be close enough, the band indexes find them without a scan
+    async fn find_near_duplicate(
+        &self,
+        url: &Url,
+        fingerprint: u64,
+    ) -> Result<Option<(String, u32)>> {
+        let mut pool = self.db.get_pg().await?;
+        let [band0, band1, band2, band3] = bands(fingerprint);
+
+        let rec = sqlx::query!(
+            r#"
+            SELECT doc_id, bit_count((simhash # $3)::bit(64)) AS "distance!"
+            FROM document
+            WHERE host = $1 AND url <> $2 AND simhash IS NOT NULL AND gone_at IS NULL
+                AND (simhash_band0 = $5 OR simhash_band1 = $6 OR simhash_band2 = $7 OR simhash_band3 = $8)
+                AND bit_count((simhash # $3)::bit(64)) <= $4
+            ORDER BY 2, created_at
+            LIMIT 1
+            "#,
+            url.host_str(),
+            url.as_str(),
+            fingerprint as i64,
+            MAX_DISTANCE as i64,
+            band0,
+            band1,
+            band2,
+            band3
+        )
+        .fetch_optional(pool.acquire().await?)
+        .await?;
+
+        Ok(rec.map(|r| (r.doc_id, r.distance as u32)))
+    }
+
+    // a near-duplicate isn't saved or embedded, a document of its own from before is tombstoned
+    async fn link_duplicate(&self, url: &Url, original: &str, distance: u32) -> Result<()> {
+        self.remove_document(url).await?;
+
+        let mut pool = self.db.get_pg().await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO near_duplicate (url, doc_id, distance)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (url) DO UPDATE SET doc_id = $2, distance = $3
+            "#,
+            url.as_str(),
+            original,
+            distance as i32
+        )
+        .execute(pool.acquire().await?)
+        .await?;
+
+        Ok(())
+    }
+
+    // tombstones the document of a page that is no longer indexed on its own, because it asks not to be
+    // or turned into a near-duplicate, kept like a removed page so search drops it
+    async fn remove_document(&self, url: &Url) -> Result<()> {
+        let mut pool = self.db.get_pg().await?;
+        let mut tx = pool.begin().await?;
+
+        let doc = sqlx::query!(
+            "UPDATE document SET gone_at = now() WHERE url = $1 AND gone_at IS NULL RETURNING doc_id",
+            url.as_str()
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(doc) = doc {
+            sqlx::query!("DELETE FROM chunk WHERE doc_id = $1", doc.doc_id)
+                .execute(&mut *tx)
+                .await?;
+            info!("remove_document: removed document {} of {url}", doc.doc_id);
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
     // sometimes some ids are not send to embedder because of closing embedder queue, this function resends them
     #[allow(dead_code)]
     pub async fn send_missing_ids(&self) -> Result<()> {
@@ -201,6 +386,7 @@ impl Parser {
                 SELECT d.doc_id
                 FROM document AS d
                 WHERE d.doc_id NOT IN (SELECT doc_id FROM chunk)
+                AND d.gone_at IS NULL
                 GROUP BY d.doc_id;
             "#
         )
@@ -237,12 +423,75 @@ impl Parser {
 
         let crawl_message = serde_json::from_slice::<CrawlMessage>(&doc.unwrap())?;
         let host = Url::parse(&crawl_message.url)?;
-        info!("parsing url {host}");
+        info!("parsing url {host} as {}", crawl_message.content_type);
+
+        let format = DocumentFormat::from_content_type(&crawl_message.content_type, &host).ok_or(
+            anyhow!(
+                "parse: unsupported content type {}",
+                crawl_message.content_type
+            ),
+        )?;
+
+        let headers = RobotsDirectives::from_headers(&crawl_message.headers);
+        let Extracted {
+            links: urls,
+            canonical,
+            title,
+            text: doc,
+            robots,
+        } = extract(&crawl_message.content, format, &host, &self.canonicalizer)?;
+        let robots = headers.merge(robots);
+        let urls = if robots.nofollow {
+            info!("not following links of {host}, nofollow");
+            vec![]
+        } else {
+            urls
+        };
 
-        let (urls, title, doc) = self.parse_document(crawl_message.content, host.clone())?;
+        // documents are keyed on their canonical url, a declared canonical is trusted only on configured sites
+        let configured = |u: &Url| self.is_allowed(u, 0);
+        let doc_url = match canonical {
+            Some(c) if configured(&c) => self.canonicalizer.canonicalize(&c),
+            Some(c) => {
+                warn!("ignoring canonical {c} of {host}, host not found in config");
+                self.canonicalizer.canonicalize(&host)
+            }
+            None => self.canonicalizer.canonicalize(&host),
+        };
 
-        let id = self.save_document(title, doc, host).await?;
-        self.save_urls(urls, crawl_message.depth as i32).await?;
+        // messages from before sites had hosts other than their own
+        let site = match crawl_message.site.as_str() {
+            "" => self
+                .config
+                .read()
+                .unwrap()
+                .site_for(&host)
+                .unwrap_or_default()
+                .to_string(),
+            site => site.to_string(),
+        };
+        self.save_urls(urls, crawl_message.depth as i32, &site, crawl_message.hops)
+            .await?;
+
+        if robots.noindex {
+            info!("not indexing {host}, noindex");
+            return self.remove_document(&doc_url).await;
+        }
+
+        // mirrors and print views of a document already saved for the host are linked to it instead
+        let fingerprint = simhash(&doc);
+        if let Some(fingerprint) = fingerprint {
+            if let Some((original, distance)) =
+                self.find_near_duplicate(&doc_url, fingerprint).await?
+            {
+                info!("{doc_url} is a near-duplicate of document {original}, distance {distance}");
+                return self.link_duplicate(&doc_url, &original, distance).await;
+            }
+        }
+
+        let id = self
+            .save_document(title, doc, doc_url, format, fingerprint)
+            .await?;
         info!("sending {id} to embedder");
         self.amq.publish(id).await?;
         Ok(())
@@ -258,13 +507,6 @@ impl AsyncConsumer for Parser {
         _basic_properties: BasicProperties,
         content: Vec<u8>,
     ) {
-        // ack explicitly if manual ack
-        if !self.auto_ack {
-            info!("ack to delivery {} on channel {}", deliver, channel);
-            let args = BasicAckArguments::new(deliver.delivery_tag(), false);
-            channel.basic_ack(args).await.unwrap();
-        }
-
         let id = String::from_utf8(content).unwrap();
         info!("received id from crawler, parsing now {id}");
         let now = Instant::now();
@@ -273,5 +515,14 @@ impl AsyncConsumer for Parser {
         } else {
             info!("parsed {id} in {}", now.elapsed().as_secs_f32());
         }
+
+        // ack once the message is done with, a shutdown or crash before this redelivers it
+        if !self.auto_ack {
+            info!("ack to delivery {} on channel {}", deliver, channel);
+            let args = BasicAckArguments::new(deliver.delivery_tag(), false);
+            if let Err(e) = channel.basic_ack(args).await {
+                error!("amq consumer::parser error while acking {id}: {e}");
+            }
+        }
     }
 }
@@ -0,0 +1,127 @@
+// page level robots directives, from <meta name="robots"> and X-Robots-Tag headers
+// robots.txt is the crawler's business, see crawler/src/robots.rs
+
+// the name we answer to in <meta name="..."> and "agent: ..." header values
+const AGENT: &str = "foxeye";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RobotsDirectives {
+    pub noindex: bool,  // don't save the document
+    pub nofollow: bool, // don't queue any of its links
+}
+
+impl RobotsDirectives {
+    // every X-Robots-Tag header of the response, names are lowercase
+    pub fn from_headers(headers: &[(String, String)]) -> Self {
+        let mut robots = RobotsDirectives::default();
+
+        for (_, value) in headers.iter().filter(|(n, _)| n == "x-robots-tag") {
+            // "googlebot: noindex" only applies to googlebot, "unavailable_after: ..." is a directive
+            let directives = match value.split_once(':') {
+                Some((agent, rest))
+                    if !agent.contains([',', ' '])
+                        && !agent.trim().eq_ignore_ascii_case("unavailable_after") =>
+                {
+                    if !agent.trim().eq_ignore_ascii_case(AGENT) {
+                        continue;
+                    }
+                    rest
+                }
+                _ => value.as_str(),
+            };
+            robots.apply(directives);
+        }
+
+        robots
+    }
+
+    // <meta name="robots" content="..."> or <meta name="foxeye" content="...">
+    pub fn from_meta(name: &str, content: &str) -> Self {
+        let mut robots = RobotsDirectives::default();
+        if name.eq_ignore_ascii_case("robots") || name.eq_ignore_ascii_case(AGENT) {
+            robots.apply(content);
+        }
+        robots
+    }
+
+    pub fn merge(self, other: Self) -> Self {
+        RobotsDirectives {
+            noindex: self.noindex || other.noindex,
+            nofollow: self.nofollow || other.nofollow,
+        }
+    }
+
+    fn apply(&mut self, directives: &str) {
+        for directive in directives.split(',').map(|d| d.trim().to_ascii_lowercase()) {
+            match directive.as_str() {
+                "noindex" => self.noindex = true,
+                "nofollow" => self.nofollow = true,
+                "none" => {
+                    self.noindex = true;
+                    self.nofollow = true;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// rel="nofollow" on a single link
+pub fn is_nofollow(rel: Option<&str>) -> bool {
+    rel.is_some_and(|r| {
+        r.split_whitespace()
+            .any(|r| r.eq_ignore_ascii_case("nofollow"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_headers() {
+        let robots = |noindex, nofollow| RobotsDirectives { noindex, nofollow };
+        let cases = [
+            (vec!["noindex"], robots(true, false)),
+            (vec!["NoIndex, NoFollow"], robots(true, true)),
+            (vec!["none"], robots(true, true)),
+            (vec!["googlebot: noindex"], robots(false, false)),
+            (vec!["foxeye: nofollow"], robots(false, true)),
+            (
+                vec!["unavailable_after: 25 Jun 2010 15:00:00 PST"],
+                robots(false, false),
+            ),
+            (vec!["noarchive", "nofollow"], robots(false, true)),
+            (vec![], robots(false, false)),
+        ];
+
+        for (values, expected) in cases {
+            let headers = values
+                .iter()
+                .map(|v| ("x-robots-tag".to_string(), v.to_string()))
+                .chain([("content-type".to_string(), "noindex".to_string())])
+                .collect::<Vec<_>>();
+            assert_eq!(
+                RobotsDirectives::from_headers(&headers),
+                expected,
+                "{values:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_meta() {
+        let meta = RobotsDirectives::from_meta("ROBOTS", "noindex, nofollow");
+        assert!(meta.noindex && meta.nofollow);
+
+        let meta = RobotsDirectives::from_meta("foxeye", "nofollow");
+        assert!(!meta.noindex && meta.nofollow);
+
+        let meta = RobotsDirectives::from_meta("googlebot", "noindex");
+        assert_eq!(meta, RobotsDirectives::default());
+
+        assert!(is_nofollow(Some("external NOFOLLOW")));
+        assert!(!is_nofollow(Some("noopener")));
+        assert!(!is_nofollow(None));
+    }
+}
@@ -0,0 +1,122 @@
+// 64 bit simhash over word shingles of a document's text
+// mirrors, print views and paginated variants of a page end up a few bits apart
+
+// words per shingle
+const SHINGLE_LEN: usize = 3;
+// fingerprints at most this many bits apart are near-duplicates, compared in the database
+pub const MAX_DISTANCE: u32 = 3;
+// width of the bands a fingerprint is split into, 64 / (MAX_DISTANCE + 1)
+const BAND_BITS: u32 = 16;
+
+// none for texts too short to tell apart from others
+pub fn simhash(text: &str) -> Option<u64> {
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect::<Vec<_>>();
+    if words.len() < SHINGLE_LEN * 2 {
+        return None;
+    }
+
+    let mut weights = [0i64; 64];
+    for shingle in words.windows(SHINGLE_LEN) {
+        let hash = fnv1a(shingle);
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if hash & (1 << bit) != 0 {
+                *weight += 1;
+            } else {
+                *weight -= 1;
+            }
+        }
+    }
+
+    let fingerprint = weights
+        .iter()
+        .enumerate()
+        .filter(|(_, w)| **w > 0)
+        .fold(0u64, |f, (bit, _)| f | (1 << bit));
+    Some(fingerprint)
+}
+
+// the fingerprint in MAX_DISTANCE + 1 bands, stored in indexed columns of document
+// fingerprints at most MAX_DISTANCE bits apart differ in at most that many bands, so they share one
+pub fn bands(fingerprint: u64) -> [i32; 4] {
+    [0, 1, 2, 3].map(|i| ((fingerprint >> (i * BAND_BITS)) & 0xffff) as i32)
+}
+
+// stable across builds and platforms unlike std's hasher, fingerprints are kept in the database
+fn fnv1a(words: &[String]) -> u64 {
+    const OFFSET: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    let mut hash = OFFSET;
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 {
+            hash = (hash ^ b' ' as u64).wrapping_mul(PRIME);
+        }
+        for b in word.bytes() {
+            hash = (hash ^ b as u64).wrapping_mul(PRIME);
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simhash() {
+        let page = "The Rust Programming Language. Chapter 4: Understanding Ownership. \
+            Ownership is Rust's most unique feature and has deep implications for the rest of \
+            the language. It enables Rust to make memory safety guarantees without needing a \
+            garbage collector, so it's important to understand how ownership works. In this \
+            chapter, we'll talk about ownership as well as several related features: borrowing, \
+            slices, and how Rust lays data out in memory. Ownership is a set of rules that \
+            govern how a Rust program manages memory. All programs have to manage the way they \
+            use a computer's memory while running. Some languages have garbage collection that \
+            regularly looks for no-longer-used memory as the program runs; in other languages, \
+            the programmer must explicitly allocate and free the memory. Rust uses a third \
+            approach: memory is managed through a system of ownership with a set of rules that \
+            the compiler checks. If any of the rules are violated, the program won't compile. \
+            None of the features of ownership will slow down your program while it's running. \
+            Because ownership is a new concept for many programmers, it does take some time to \
+            get used to. The good news is that the more experienced you become with Rust and \
+            the rules of the ownership system, the easier you'll find it to naturally develop \
+            code that is safe and efficient. Keep at it!";
+        let print_view = format!("{page} Print this page");
+        let other = "Vulkan Tutorial. Drawing a triangle. Setup. Base code. General structure. \
+            In the previous chapter you've created a Vulkan project with all of the proper \
+            configuration and tested it with the sample code. In this chapter we're starting \
+            from scratch with the following code that creates a window and a Vulkan instance.";
+
+        let distance = |a: u64, b: u64| (a ^ b).count_ones();
+        let a = simhash(page).unwrap();
+        let b = simhash(&print_view).unwrap();
+        let c = simhash(other).unwrap();
+
+        assert_eq!(simhash(&page.to_uppercase()), Some(a));
+        assert!(distance(a, b) <= MAX_DISTANCE, "{}", distance(a, b));
+        assert!(distance(a, c) > MAX_DISTANCE, "{}", distance(a, c));
+        assert_eq!(simhash("too short"), None);
+    }
+
+    #[test]
+    fn test_bands() {
+        assert_eq!(bands(0x0004_0003_0002_0001), [1, 2, 3, 4]);
+        assert_eq!(bands(u64::MAX), [0xffff; 4]);
+
+        // any MAX_DISTANCE flipped bits leave at least one band as it was
+        let fingerprint = 0x9e37_79b9_7f4a_7c15;
+        for (a, b, c) in [(0, 16, 32), (15, 31, 47), (48, 49, 63), (5, 21, 40)] {
+            let near = fingerprint ^ (1 << a) ^ (1 << b) ^ (1 << c);
+            let shared = bands(fingerprint)
+                .iter()
+                .zip(bands(near))
+                .filter(|(x, y)| **x == *y)
+                .count();
+            assert!(shared >= 1, "{a} {b} {c}");
+        }
+    }
+}
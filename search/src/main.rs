@@ -20,6 +20,7 @@ use tower_http::trace::TraceLayer;
 use tracing::{error, info, info_span, Span};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
+use utils::shutdown_signal;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -91,7 +92,10 @@ async fn main() -> Result<()> {
 
     let listener = TcpListener::bind(addr).await?;
 
-    axum::serve(listener, router).await?;
+    // in-flight searches are answered before exiting
+    axum::serve(listener, router)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
 
     Ok(())
 }
@@ -6,6 +6,7 @@ use sqlx::{Acquire, FromRow};
 use std::iter::Iterator;
 use std::string::ToString;
 use tracing::{warn};
+use url::Url;
 
 use crate::misc::STOPWORDS;
 use db::Db;
@@ -75,6 +76,7 @@ impl Searcher {
                     document d ON rc.doc_id = d.doc_id
                 WHERE
                     rc.rank = 1
+                    AND d.gone_at IS NULL
                 ORDER BY
                     rc.similarity DESC
                 LIMIT $2 OFFSET $3;
@@ -163,6 +165,7 @@ impl Searcher {
                     || chunk_start > content.len()
                 {
                     res.push(SearchResult {
+                        path: local_path(&url),
                         url: url.clone(),
                         score,
                         summary: "".to_string(),
@@ -183,6 +186,7 @@ impl Searcher {
                     .unwrap_or(summary);
 
                 res.push(SearchResult {
+                    path: local_path(&url),
                     url,
                     score,
                     summary,
@@ -195,6 +199,12 @@ impl Searcher {
     }
 }
 
+fn local_path(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok().filter(|u| u.scheme() == "file")?;
+    let path = url.to_file_path().ok()?;
+    Some(path.display().to_string())
+}
+
 #[derive(Debug, FromRow)]
 pub struct Chunk {
     pub chunk_id: Option<String>,
@@ -216,6 +226,7 @@ pub struct SearchInput {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchResult {
     pub url: String,
+    pub path: Option<String>, // documents of file:// sites link back to the file
     pub score: f64,
     pub summary: String,
     pub title: String,
@@ -1,22 +1,23 @@
 use std::fmt::{Debug, Formatter};
+use std::future::Future;
 
 use amqprs::callbacks::{DefaultChannelCallback, DefaultConnectionCallback};
 use amqprs::channel::{
-    BasicAckArguments, BasicConsumeArguments, BasicPublishArguments, Channel,
-    ExchangeDeclareArguments, QueueBindArguments, QueueDeclareArguments,
+    BasicAckArguments, BasicCancelArguments, BasicConsumeArguments, BasicPublishArguments,
+    BasicQosArguments, Channel, ExchangeDeclareArguments, QueueBindArguments,
+    QueueDeclareArguments,
 };
 use amqprs::connection::{Connection, OpenConnectionArguments};
 use amqprs::consumer::AsyncConsumer;
 use amqprs::{BasicProperties, Deliver};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{self, UnboundedSender};
 use tracing::{error, info};
 
 #[derive(Clone)]
 pub struct RabbitMQ {
     pub channel: Channel,
-    #[allow(dead_code)]
     pub connection: Connection,
     queue: String,
     routing_key: String,
@@ -36,6 +37,9 @@ impl Debug for RabbitMQ {
 }
 
 impl RabbitMQ {
+    // unacked messages a consumer is handed at a time, they go back to the queue if it dies
+    const PREFETCH: u16 = 8;
+
     pub async fn new(
         uri: &str,
         queue: &str,
@@ -91,6 +95,49 @@ impl RabbitMQ {
         })
     }
 
+    // every instance gets its own copy of each message, through a fanout exchange and a private queue
+    pub async fn broadcast(uri: &str, consumer_tag: &str, exchange_name: &str) -> Result<RabbitMQ> {
+        let args: OpenConnectionArguments = uri.try_into().unwrap();
+        let connection = Connection::open(&args).await?;
+
+        connection
+            .register_callback(DefaultConnectionCallback)
+            .await?;
+
+        let channel = connection.open_channel(None).await?;
+
+        channel.register_callback(DefaultChannelCallback).await?;
+
+        let (queue_name, _msg_count, _consumer_count) = match channel
+            .queue_declare(QueueDeclareArguments::exclusive_server_named())
+            .await?
+        {
+            Some(a) => a,
+            None => {
+                return Err(anyhow!("queue declare returned None"));
+            }
+        };
+
+        let exchange_name = exchange_name.to_string();
+
+        channel
+            .exchange_declare(ExchangeDeclareArguments::new(&exchange_name, "fanout"))
+            .await?;
+
+        channel
+            .queue_bind(QueueBindArguments::new(&queue_name, &exchange_name, ""))
+            .await?;
+
+        Ok(RabbitMQ {
+            channel,
+            connection,
+            queue: queue_name,
+            routing_key: String::new(),
+            exchange_name,
+            consumer_tag: consumer_tag.to_string(),
+        })
+    }
+
     pub async fn publish(&self, content: String) -> Result<()> {
         let args = BasicPublishArguments::new(&self.exchange_name, &self.routing_key);
         self.channel
@@ -135,6 +182,74 @@ impl RabbitMQ {
 
         Ok(res)
     }
+
+    // consumes until `shutdown` resolves, then cancels the consumer, lets it finish the messages
+    // it was handed and closes the channel, anything unacked by then is redelivered by the broker
+    pub async fn consume_until<C, F>(
+        &self,
+        consumer_tag: &str,
+        auto_ack: bool,
+        consumer: C,
+        shutdown: F,
+    ) -> Result<()>
+    where
+        C: AsyncConsumer + Send + 'static,
+        F: Future<Output = ()>,
+    {
+        self.channel
+            .basic_qos(BasicQosArguments::new(0, Self::PREFETCH, false))
+            .await?;
+
+        // the consumer task drops its consumer, and with it `done`, once it has nothing left to process
+        let (done, mut finished) = mpsc::channel::<()>(1);
+        let tag = self
+            .consume(
+                consumer_tag,
+                auto_ack,
+                Draining {
+                    consumer,
+                    _done: done,
+                },
+            )
+            .await?;
+
+        shutdown.await;
+        info!("amq: cancelling consumer {tag}, waiting for in-flight messages");
+        self.channel
+            .basic_cancel(BasicCancelArguments::new(&tag))
+            .await?;
+        finished.recv().await;
+
+        self.close().await
+    }
+
+    pub async fn close(&self) -> Result<()> {
+        self.channel.clone().close().await?;
+        self.connection.clone().close().await?;
+        info!("amq: closed connection of {}", self.consumer_tag);
+
+        Ok(())
+    }
+}
+
+struct Draining<C> {
+    consumer: C,
+    _done: mpsc::Sender<()>,
+}
+
+#[async_trait]
+impl<C: AsyncConsumer + Send> AsyncConsumer for Draining<C> {
+    async fn consume(
+        &mut self,
+        channel: &Channel,
+        deliver: Deliver,
+        basic_properties: BasicProperties,
+        content: Vec<u8>,
+    ) {
+        self.consumer
+            .consume(channel, deliver, basic_properties, content)
+            .await
+    }
 }
 
 struct Consumer {
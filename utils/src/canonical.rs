@@ -0,0 +1,227 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use anyhow::Result;
+use url::Url;
+
+// query params that only track where a visitor came from, a trailing '*' matches by prefix
+const DEFAULT_TRACKING_PARAMS: &[&str] = &[
+    "utm_*", "gclid", "dclid", "fbclid", "msclkid", "yclid", "mc_cid", "mc_eid", "_ga", "_gl",
+    "igshid",
+];
+
+// normalises urls so that variants of the same page share one key in redis, crawler_queue and document
+#[derive(Debug, Clone)]
+pub struct Canonicalizer {
+    tracking_params: Vec<String>,
+}
+
+impl Default for Canonicalizer {
+    fn default() -> Self {
+        Canonicalizer::new(vec![])
+    }
+}
+
+impl Canonicalizer {
+    // `tracking_params` are stripped in addition to the defaults
+    pub fn new(tracking_params: Vec<String>) -> Self {
+        let mut params = DEFAULT_TRACKING_PARAMS
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>();
+        params.extend(tracking_params.into_iter().map(|p| p.to_lowercase()));
+
+        Canonicalizer {
+            tracking_params: params,
+        }
+    }
+
+    // reads the optional "tracking_params" list from sites.json
+    pub fn load_config() -> Result<Self> {
+        if !Path::new("sites.json").exists() {
+            return Ok(Canonicalizer::default());
+        }
+        let sites = read_to_string("sites.json")?;
+        let val = serde_json::from_str::<serde_json::Value>(&sites)?;
+
+        let params = match val.get("tracking_params") {
+            Some(v) => serde_json::from_value::<Vec<String>>(v.to_owned())?,
+            None => vec![],
+        };
+
+        Ok(Canonicalizer::new(params))
+    }
+
+    // the url as it is fetched, without fragment and tracking params and with a sorted query
+    // directories keep one trailing slash, servers redirect /dir to /dir/ and that costs a request
+    // a file can't have one, /page.html/ is /page.html
+    pub fn normalize(&self, url: &Url) -> Url {
+        // scheme and host case, default ports and dot-segments are already normalised by Url::parse
+        let mut url = url.clone();
+        url.set_fragment(None);
+
+        if url.cannot_be_a_base() {
+            return url;
+        }
+
+        let path = url.path();
+        if path.len() > 1 && path.ends_with('/') {
+            let trimmed = path.trim_end_matches('/');
+            let is_file = trimmed
+                .rsplit('/')
+                .next()
+                .and_then(|segment| segment.rsplit_once('.'))
+                .is_some_and(|(name, ext)| !name.is_empty() && !ext.is_empty());
+            let path = match trimmed {
+                "" => "/".to_string(),
+                p if is_file => p.to_string(),
+                p => format!("{p}/"),
+            };
+            url.set_path(&path);
+        }
+
+        let mut pairs = url
+            .query_pairs()
+            .into_owned()
+            .filter(|(k, _)| !self.is_tracking(k))
+            .collect::<Vec<_>>();
+
+        if pairs.is_empty() {
+            url.set_query(None);
+        } else {
+            pairs.sort();
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+
+        url
+    }
+
+    // the key of a page in redis, crawl_state and document, /dir and /dir/ are the same page
+    // crawler_queue keeps the normalized url, that is what gets fetched
+    pub fn canonicalize(&self, url: &Url) -> Url {
+        let mut url = self.normalize(url);
+        if url.cannot_be_a_base() {
+            return url;
+        }
+
+        let path = url.path();
+        if path.len() > 1 && path.ends_with('/') {
+            let path = path.trim_end_matches('/').to_string();
+            url.set_path(&path);
+        }
+
+        url
+    }
+
+    // resolves an href found on a page against its base url, none if it isn't a crawlable link
+    pub fn resolve(&self, base: &Url, href: &str) -> Option<Url> {
+        let href = href.trim();
+        if href.is_empty() || href.starts_with('#') {
+            return None;
+        }
+
+        let url = base.join(href).ok()?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return None;
+        }
+
+        Some(self.normalize(&url))
+    }
+
+    fn is_tracking(&self, key: &str) -> bool {
+        let key = key.to_lowercase();
+        self.tracking_params
+            .iter()
+            .any(|p| match p.strip_suffix('*') {
+                Some(prefix) => key.starts_with(prefix),
+                None => key == *p,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonicalize() {
+        let c = Canonicalizer::new(vec!["sessionid".to_string(), "ref_*".to_string()]);
+
+        let cases = [
+            ("http://x/a", "http://x/a"),
+            ("HTTP://X/a/", "http://x/a"),
+            ("http://x/a//", "http://x/a"),
+            ("http://x/a.html/", "http://x/a.html"),
+            ("http://x/a#frag", "http://x/a"),
+            ("http://x:80/a", "http://x/a"),
+            ("https://x:443/", "https://x/"),
+            ("https://x:8443/a", "https://x:8443/a"),
+            ("http://x/a/./b/../c//", "http://x/a/c"),
+            ("http://x/a?", "http://x/a"),
+            ("http://x/a?utm_source=foo&utm_medium=bar", "http://x/a"),
+            ("http://x/a?b=2&a=1&UTM_Campaign=x", "http://x/a?a=1&b=2"),
+            ("http://x/a?fbclid=1&q=rust+lang", "http://x/a?q=rust+lang"),
+            (
+                "http://x/a?SessionId=1&ref_page=2&ref=3",
+                "http://x/a?ref=3",
+            ),
+            ("http://x/?b=1&a=2&a=1", "http://x/?a=1&a=2&b=1"),
+        ];
+
+        for (url, expected) in cases {
+            let url = Url::parse(url).unwrap();
+            assert_eq!(c.canonicalize(&url).as_str(), expected, "{url}");
+        }
+
+        // a directory is one page with or without its trailing slash
+        let dir = Url::parse("http://x/a").unwrap();
+        let slashed = Url::parse("HTTP://X/a/").unwrap();
+        assert_eq!(c.canonicalize(&dir), c.canonicalize(&slashed));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let c = Canonicalizer::default();
+
+        let cases = [
+            ("http://x/a", "http://x/a"),
+            ("HTTP://X/a/", "http://x/a/"),
+            ("http://x/a//", "http://x/a/"),
+            ("http://x/a.html/", "http://x/a.html"),
+            ("http://x/.well-known/", "http://x/.well-known/"),
+            ("http://x/a/?utm_source=x&b=1#top", "http://x/a/?b=1"),
+            ("https://x/", "https://x/"),
+        ];
+
+        for (url, expected) in cases {
+            let url = Url::parse(url).unwrap();
+            assert_eq!(c.normalize(&url).as_str(), expected, "{url}");
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let c = Canonicalizer::default();
+        let base = Url::parse("https://x/docs/guide/").unwrap();
+
+        let cases = [
+            ("intro.html", Some("https://x/docs/guide/intro.html")),
+            ("../api/?utm_source=nav", Some("https://x/docs/api/")),
+            ("/about#team", Some("https://x/about")),
+            ("//y/path", Some("https://y/path")),
+            ("http://Y/", Some("http://y/")),
+            ("#top", None),
+            ("  ", None),
+            ("mailto:a@x", None),
+            ("javascript:void(0)", None),
+        ];
+
+        for (href, expected) in cases {
+            assert_eq!(
+                c.resolve(&base, href).as_ref().map(Url::as_str),
+                expected,
+                "{href}"
+            );
+        }
+    }
+}
@@ -1,39 +1,99 @@
 pub mod amq;
+pub mod canonical;
+pub mod scope;
+pub mod shutdown;
+pub mod traps;
 
 pub use amq::RabbitMQ;
 pub use amqprs;
 pub use async_trait;
+pub use canonical::Canonicalizer;
+pub use scope::{matches_domain, site_key, Scope};
+pub use shutdown::shutdown_signal;
+pub use traps::{detect_trap, Trap};
+
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 use url::Url;
 
+// fanout exchange the crawler's admin api announces site changes on
+pub const CONTROL_EXCHANGE: &str = "foxeye.control";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlMessage {
+    // the row of `host` in the sites table was added, changed or removed
+    SiteChanged { host: String },
+}
+
+impl ControlMessage {
+    // publishes on a RabbitMQ::broadcast channel of CONTROL_EXCHANGE
+    pub async fn send(&self, control: &RabbitMQ) -> anyhow::Result<()> {
+        control.publish(serde_json::to_string(self)?).await
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CrawlUrl {
     pub url: Url,
     pub depth: u32,
+    pub attempts: u32,                  // failed fetch attempts so far
+    pub hops: u32,                      // links followed outside of the site's own hosts
+    pub refresh_hint: Option<Duration>, // the changefreq of the sitemap that listed it
 }
 
 impl CrawlUrl {
     pub fn new(url: Url, depth: u32) -> CrawlUrl {
-        CrawlUrl { url, depth }
+        CrawlUrl {
+            url,
+            depth,
+            attempts: 0,
+            hops: 0,
+            refresh_hint: None,
+        }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CrawlMessage {
     pub id: String,
-    pub content: String,
+    pub content: String, // utf-8 text, base64 for binary formats like pdf
     pub depth: u32,
     pub url: String,
+    pub content_type: String, // mime type without parameters, e.g. text/html
+    #[serde(default)]
+    pub site: String, // host of the configured site the page was crawled for
+    #[serde(default)]
+    pub hops: u32,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>, // response headers, names lowercase, repeated headers repeat
 }
 
 impl CrawlMessage {
-    pub fn new(id: String, content: String, depth: u32, url: String) -> CrawlMessage {
+    pub fn new(
+        id: String,
+        content: String,
+        depth: u32,
+        url: String,
+        content_type: String,
+        site: String,
+        hops: u32,
+    ) -> CrawlMessage {
         CrawlMessage {
             id,
             content,
             depth,
             url,
+            content_type,
+            site,
+            hops,
+            headers: vec![],
         }
     }
+
+    pub fn with_headers(mut self, headers: Vec<(String, String)>) -> CrawlMessage {
+        self.headers = headers;
+        self
+    }
 }
@@ -0,0 +1,188 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use url::Url;
+
+// patterns starting with this are regexes searched for anywhere in the path and query,
+// everything else is a glob where '*' matches any run of characters
+const REGEX_PREFIX: &str = "re:";
+
+// the part of a host a site covers, checked by the crawler before fetching and by the parser before queueing
+#[derive(Debug, Clone)]
+pub struct Scope {
+    path_prefix: String,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl Scope {
+    // `path_prefix` defaults to the directory of the seed url
+    pub fn new(
+        seed: &Url,
+        path_prefix: Option<&str>,
+        include: &[String],
+        exclude: &[String],
+    ) -> Result<Self> {
+        let path_prefix = match path_prefix {
+            Some(p) if p.starts_with('/') => p.to_string(),
+            Some(p) => return Err(anyhow!("path prefix {p} doesn't start with '/'")),
+            None => Self::default_prefix(seed),
+        };
+
+        Ok(Scope {
+            path_prefix,
+            include: include.iter().map(|p| pattern(p)).collect::<Result<_>>()?,
+            exclude: exclude.iter().map(|p| pattern(p)).collect::<Result<_>>()?,
+        })
+    }
+
+    // "/book/" for https://x/book/ and https://x/book/index.html, "/" for https://x/book
+    pub fn default_prefix(seed: &Url) -> String {
+        let path = seed.path();
+        match path.rfind('/') {
+            Some(i) => path[..=i].to_string(),
+            None => "/".to_string(),
+        }
+    }
+
+    pub fn path_prefix(&self) -> &str {
+        &self.path_prefix
+    }
+
+    // hosts a site covers through its domains keep its patterns, the path prefix belongs to the seed host
+    pub fn for_other_host(&self) -> Scope {
+        Scope {
+            path_prefix: "/".to_string(),
+            ..self.clone()
+        }
+    }
+
+    pub fn allows(&self, url: &Url) -> bool {
+        let path = url.path();
+        // a link to /book is the /book/ directory itself, the server redirects it there
+        let in_prefix = path.starts_with(&self.path_prefix)
+            || path == self.path_prefix.trim_end_matches('/')
+            || self.path_prefix == "/";
+        if !in_prefix {
+            return false;
+        }
+
+        let target = match url.query() {
+            Some(query) => format!("{path}?{query}"),
+            None => path.to_string(),
+        };
+
+        (self.include.is_empty() || self.include.iter().any(|r| r.is_match(&target)))
+            && !self.exclude.iter().any(|r| r.is_match(&target))
+    }
+}
+
+// "*.rust-lang.org" matches rust-lang.org and all of its subdomains, anything else only the host itself
+pub fn matches_domain(host: &str, pattern: &str) -> bool {
+    let host = host.trim_end_matches('.');
+    match pattern.strip_prefix("*.") {
+        Some(domain) => {
+            let (host, domain) = (host.as_bytes(), domain.as_bytes());
+            host.eq_ignore_ascii_case(domain)
+                || host.len() > domain.len()
+                    && host[host.len() - domain.len() - 1] == b'.'
+                    && host[host.len() - domain.len()..].eq_ignore_ascii_case(domain)
+        }
+        None => host.eq_ignore_ascii_case(pattern),
+    }
+}
+
+// key of a site in the sites table, its host, or its url for file:// sites which have none
+pub fn site_key(url: &Url) -> Option<String> {
+    match url.scheme() {
+        "file" => Some(url.to_string()),
+        _ => url.host_str().map(|h| h.to_string()),
+    }
+}
+
+fn pattern(p: &str) -> Result<Regex> {
+    if let Some(re) = p.strip_prefix(REGEX_PREFIX) {
+        return Regex::new(re).map_err(|e| anyhow!("invalid regex {re}: {e}"));
+    }
+
+    // globs match the whole path and query
+    let glob = p
+        .split('*')
+        .map(regex::escape)
+        .collect::<Vec<_>>()
+        .join(".*");
+    Ok(Regex::new(&format!("^{glob}$"))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_prefix() {
+        let cases = [
+            ("https://doc.rust-lang.org/book/", "/book/"),
+            ("https://doc.rust-lang.org/book/ch01.html", "/book/"),
+            ("https://en.wikipedia.org/wiki/Main_Page", "/wiki/"),
+            ("https://aur.archlinux.org/packages", "/"),
+            ("https://x", "/"),
+        ];
+
+        for (seed, expected) in cases {
+            let seed = Url::parse(seed).unwrap();
+            assert_eq!(Scope::default_prefix(&seed), expected, "{seed}");
+        }
+    }
+
+    #[test]
+    fn test_matches_domain() {
+        let cases = [
+            ("blog.rust-lang.org", "*.rust-lang.org", true),
+            ("rust-lang.org", "*.rust-lang.org", true),
+            ("a.b.rust-lang.org", "*.rust-lang.org", true),
+            ("BLOG.Rust-Lang.org", "*.rust-lang.org", true),
+            ("notrust-lang.org", "*.rust-lang.org", false),
+            ("rust-lang.org.evil.com", "*.rust-lang.org", false),
+            ("w3schools.com", "w3schools.com", true),
+            ("www.w3schools.com", "w3schools.com", false),
+        ];
+
+        for (host, pattern, expected) in cases {
+            assert_eq!(matches_domain(host, pattern), expected, "{host} {pattern}");
+        }
+    }
+
+    #[test]
+    fn test_allows() {
+        let seed = Url::parse("https://x/wiki/Main_Page").unwrap();
+        let scope = Scope::new(
+            &seed,
+            None,
+            &[],
+            &[
+                "*action=history*".to_string(),
+                "re:^/wiki/(Special|Talk):".to_string(),
+            ],
+        )
+        .unwrap();
+
+        let cases = [
+            ("https://x/wiki/Rust", true),
+            ("https://x/wiki", true),
+            ("https://x/wiki/Rust?action=history", false),
+            ("https://x/wiki/Special:Random", false),
+            ("https://x/wiki/Talk:Rust", false),
+            ("https://x/wikipedia", false),
+            ("https://x/w/index.php?title=Rust", false),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(scope.allows(&Url::parse(url).unwrap()), expected, "{url}");
+        }
+
+        let scope = Scope::new(&seed, Some("/"), &["/docs/*.html".to_string()], &[]).unwrap();
+        assert!(scope.allows(&Url::parse("https://x/docs/a/b.html").unwrap()));
+        assert!(!scope.allows(&Url::parse("https://x/docs/a.pdf").unwrap()));
+
+        assert!(Scope::new(&seed, Some("docs"), &[], &[]).is_err());
+        assert!(Scope::new(&seed, None, &["re:(".to_string()], &[]).is_err());
+    }
+}
@@ -0,0 +1,34 @@
+use std::future::pending;
+
+use tracing::{error, info};
+
+// resolves on SIGTERM or SIGINT, whichever comes first
+pub async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("shutdown: error while listening for SIGINT {e}");
+            pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut s) => {
+                s.recv().await;
+            }
+            Err(e) => {
+                error!("shutdown: error while listening for SIGTERM {e}");
+                pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => info!("shutdown: received SIGINT"),
+        _ = terminate => info!("shutdown: received SIGTERM"),
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use url::Url;
+
+// urls generated without end by calendars, session ids and relative links gone wrong
+const MAX_URL_LEN: usize = 512;
+const MAX_QUERY_PARAMS: usize = 8;
+// a path segment showing up this often, like /a/b/a/b/a/b, is a loop of relative links
+const MAX_SEGMENT_REPEATS: usize = 3;
+// distinct query strings of one path, checked against crawler_queue at enqueue
+pub const MAX_QUERY_VARIANTS: usize = 50;
+
+const SESSION_PARAMS: &[&str] = &[
+    "jsessionid",
+    "phpsessid",
+    "aspsessionid",
+    "sessionid",
+    "session_id",
+    "sessid",
+    "sid",
+    "cfid",
+    "cftoken",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Trap {
+    UrlTooLong,
+    TooManyParams,
+    RepeatingSegments,
+    SessionId,
+    TooManyVariants,
+}
+
+impl Trap {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Trap::UrlTooLong => "url too long",
+            Trap::TooManyParams => "too many query params",
+            Trap::RepeatingSegments => "repeating path segments",
+            Trap::SessionId => "session id",
+            Trap::TooManyVariants => "too many query variants",
+        }
+    }
+}
+
+// checks that need nothing but the url, too many variants is up to the caller
+pub fn detect_trap(url: &Url) -> Option<Trap> {
+    if url.as_str().len() > MAX_URL_LEN {
+        return Some(Trap::UrlTooLong);
+    }
+
+    let params = url.query_pairs().count();
+    if params > MAX_QUERY_PARAMS {
+        return Some(Trap::TooManyParams);
+    }
+
+    // ;jsessionid=... in the path or a session param in the query
+    let path = url.path().to_ascii_lowercase();
+    let in_path = path.contains(";jsessionid=") || path.contains(";sid=");
+    let in_query = url.query_pairs().any(|(k, _)| {
+        let k = k.to_ascii_lowercase();
+        SESSION_PARAMS
+            .iter()
+            .any(|p| k == *p || k.starts_with("aspsessionid"))
+    });
+    if in_path || in_query {
+        return Some(Trap::SessionId);
+    }
+
+    let mut counts = HashMap::new();
+    for segment in url.path_segments().into_iter().flatten() {
+        if segment.is_empty() {
+            continue;
+        }
+        let count = counts.entry(segment).or_insert(0);
+        *count += 1;
+        if *count >= MAX_SEGMENT_REPEATS {
+            return Some(Trap::RepeatingSegments);
+        }
+    }
+
+    None
+}
+
+// urls sharing this differ only in their query string
+pub fn query_variant_key(url: &Url) -> &str {
+    url.as_str().split('?').next().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_trap() {
+        let long = format!("https://x/{}", "a".repeat(MAX_URL_LEN));
+        let cases = [
+            ("https://x/wiki/Rust", None),
+            ("https://x/a/b?page=2&sort=asc", None),
+            ("https://x/docs/docs/intro", None),
+            (long.as_str(), Some(Trap::UrlTooLong)),
+            (
+                "https://x/s?a=1&b=2&c=3&d=4&e=5&f=6&g=7&h=8&i=9",
+                Some(Trap::TooManyParams),
+            ),
+            ("https://x/a/b/a/b/a/b", Some(Trap::RepeatingSegments)),
+            (
+                "https://x/cal/2024/cal/2025/cal",
+                Some(Trap::RepeatingSegments),
+            ),
+            ("https://x/shop;jsessionid=ABC123", Some(Trap::SessionId)),
+            ("https://x/page?PHPSESSID=abc", Some(Trap::SessionId)),
+            (
+                "https://x/page?ASPSESSIONIDQATSBTQD=abc",
+                Some(Trap::SessionId),
+            ),
+            ("https://x/page?side=left", None),
+        ];
+
+        for (url, expected) in cases {
+            assert_eq!(detect_trap(&Url::parse(url).unwrap()), expected, "{url}");
+        }
+    }
+
+    #[test]
+    fn test_query_variant_key() {
+        let url = Url::parse("https://x/cal?month=5&year=2024").unwrap();
+        assert_eq!(query_variant_key(&url), "https://x/cal");
+        let url = Url::parse("https://x/cal").unwrap();
+        assert_eq!(query_variant_key(&url), "https://x/cal");
+    }
+}